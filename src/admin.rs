@@ -0,0 +1,157 @@
+use std::{collections::HashMap, env, sync::Arc};
+
+use log::{error, info};
+use serde_json::json;
+use uuid::Uuid;
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::{
+    limits,
+    payments::{pay_ln_address, remove_review},
+    Server,
+};
+
+// token to the admin it belongs to. ADMIN_TOKENS is comma separated name:token pairs, one per
+// admin so the audit log knows who did what, ADMIN_TOKEN is a single token for "admin"
+pub fn admin_tokens() -> HashMap<String, String> {
+    let mut tokens = HashMap::new();
+
+    if let Some(token) = env::var("ADMIN_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+    {
+        tokens.insert(token, "admin".to_string());
+    }
+
+    for pair in env::var("ADMIN_TOKENS").unwrap_or_default().split(',') {
+        if pair.trim().is_empty() {
+            continue;
+        }
+
+        match pair.trim().split_once(':') {
+            Some((name, token)) if !name.trim().is_empty() && !token.trim().is_empty() => {
+                tokens.insert(token.trim().to_string(), name.trim().to_string());
+            }
+            // the entry could be a token, it stays out of the logs
+            _ => error!("Invalid ADMIN_TOKENS entry, expected name:token"),
+        }
+    }
+
+    tokens
+}
+
+// admin routes only exist when an admin token is set and the request carries one as a bearer
+// token
+pub fn with_admin(
+    server: Arc<Server>,
+) -> impl Filter<Extract = (Arc<Server>,), Error = Rejection> + Clone {
+    with_admin_actor(server).map(|server, _actor: String| server)
+}
+
+// like `with_admin`, along with the admin the token belongs to
+pub fn with_admin_actor(
+    server: Arc<Server>,
+) -> impl Filter<Extract = (Arc<Server>, String), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::any().map(move || server.clone()))
+        .and_then(|auth: Option<String>, server: Arc<Server>| async move {
+            let actor = auth
+                .as_deref()
+                .and_then(|auth| auth.strip_prefix("Bearer "))
+                .and_then(|token| server.admin_tokens.get(token))
+                .cloned();
+
+            match actor {
+                Some(actor) => Ok((server, actor)),
+                None => Err(warp::reject::not_found()),
+            }
+        })
+        .untuple_one()
+}
+
+pub fn routes(
+    server: Arc<Server>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let admin = with_admin(server);
+
+    let list_reviews = warp::path!("admin" / "reviews")
+        .and(warp::get())
+        .and(admin.clone())
+        .and_then(list_reviews);
+
+    let approve_review = warp::path!("admin" / "reviews" / Uuid / "approve")
+        .and(warp::post())
+        .and(admin.clone())
+        .and_then(approve_review);
+
+    let reject_review = warp::path!("admin" / "reviews" / Uuid / "reject")
+        .and(warp::post())
+        .and(admin.clone())
+        .and_then(reject_review);
+
+    let connections = warp::path!("admin" / "connections")
+        .and(warp::get())
+        .and(admin)
+        .and_then(connections);
+
+    list_reviews
+        .or(approve_review)
+        .or(reject_review)
+        .or(connections)
+}
+
+async fn list_reviews(server: Arc<Server>) -> Result<impl Reply, Rejection> {
+    let review_queue = server.review_queue.lock().await;
+    let mut reviews: Vec<_> = review_queue.values().cloned().collect();
+    reviews.sort_by_key(|review| review.created);
+
+    Ok(warp::reply::json(&reviews))
+}
+
+async fn approve_review(id: Uuid, server: Arc<Server>) -> Result<impl Reply, Rejection> {
+    match remove_review(&server, id).await {
+        Some(review) => {
+            info!(
+                "Approved review {}: {} msats to {:?}",
+                id, review.amount, review.name
+            );
+            let ln_address = if review.ln_address.is_empty() {
+                review.name
+            } else {
+                review.ln_address
+            };
+            pay_ln_address(server.clone(), review.player_id, ln_address, review.amount).await;
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({ "status": "approved" })),
+                StatusCode::OK,
+            ))
+        }
+        None => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({ "error": "unknown review" })),
+            StatusCode::NOT_FOUND,
+        )),
+    }
+}
+
+async fn reject_review(id: Uuid, server: Arc<Server>) -> Result<impl Reply, Rejection> {
+    match remove_review(&server, id).await {
+        Some(review) => {
+            info!(
+                "Rejected review {}: {} msats to {:?}",
+                id, review.amount, review.name
+            );
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({ "status": "rejected" })),
+                StatusCode::OK,
+            ))
+        }
+        None => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({ "error": "unknown review" })),
+            StatusCode::NOT_FOUND,
+        )),
+    }
+}
+
+async fn connections(server: Arc<Server>) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&limits::snapshot(&server).await))
+}
@@ -0,0 +1,278 @@
+use std::{collections::VecDeque, env, time::Duration};
+
+use glam::Vec2;
+
+use crate::game_loop::{ObjectPos, PlayerEntity};
+
+const MAX_INTERVALS: usize = 64;
+const MIN_SAMPLES: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct AnticheatConfig {
+    pub enabled: bool,
+    pub min_timing_variation: f32,
+    pub max_on_target_ratio: f32,
+    pub min_reaction_ticks: u64,
+    pub max_session: Duration,
+    pub flag_score: u32,
+}
+
+impl AnticheatConfig {
+    pub fn from_env() -> Self {
+        let enabled = env::var("BOT_DETECTION").as_deref() == Ok("true");
+
+        let min_timing_variation = env::var("BOT_MIN_TIMING_VARIATION")
+            .ok()
+            .and_then(|variation| variation.parse().ok())
+            .unwrap_or(0.05);
+
+        let max_on_target_ratio = env::var("BOT_MAX_ON_TARGET_RATIO")
+            .ok()
+            .and_then(|ratio| ratio.parse().ok())
+            .unwrap_or(0.9);
+
+        let min_reaction_ticks = env::var("BOT_MIN_REACTION_TICKS")
+            .ok()
+            .and_then(|ticks| ticks.parse().ok())
+            .unwrap_or(2);
+
+        let max_session = env::var("BOT_MAX_SESSION_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30 * 60));
+
+        let flag_score = env::var("BOT_FLAG_SCORE")
+            .ok()
+            .and_then(|score| score.parse().ok())
+            .unwrap_or(2);
+
+        Self {
+            enabled,
+            min_timing_variation,
+            max_on_target_ratio,
+            min_reaction_ticks,
+            max_session,
+            flag_score,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InputStats {
+    pub last_tick: Option<u64>,
+    pub intervals: VecDeque<u64>,
+    pub inputs: u64,
+    pub on_target: u64,
+    pub fast_reactions: u64,
+}
+
+impl InputStats {
+    // bolts only fall straight down, so aiming at one means matching its x
+    pub fn record(&mut self, tick: u64, target: Vec2, bolts: &[ObjectPos], min_reaction: u64) {
+        if let Some(last_tick) = self.last_tick {
+            self.intervals.push_back(tick.saturating_sub(last_tick));
+            if self.intervals.len() > MAX_INTERVALS {
+                self.intervals.pop_front();
+            }
+        }
+        self.last_tick = Some(tick);
+        self.inputs += 1;
+
+        if let Some(bolt) = bolts
+            .iter()
+            .find(|bolt| (bolt.pos.x - target.x).abs() < 5.0 && bolt.tick <= tick)
+        {
+            self.on_target += 1;
+
+            if tick - bolt.tick < min_reaction {
+                self.fast_reactions += 1;
+            }
+        }
+    }
+
+    fn timing_variation(&self) -> Option<f32> {
+        if self.intervals.len() < MIN_SAMPLES {
+            return None;
+        }
+
+        let n = self.intervals.len() as f32;
+        let mean = self.intervals.iter().sum::<u64>() as f32 / n;
+        if mean == 0.0 {
+            return Some(0.0);
+        }
+
+        let variance = self
+            .intervals
+            .iter()
+            .map(|interval| (*interval as f32 - mean).powi(2))
+            .sum::<f32>()
+            / n;
+
+        Some(variance.sqrt() / mean)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BotScore {
+    pub score: u32,
+    pub reasons: Vec<String>,
+}
+
+pub fn assess(player: &PlayerEntity, config: &AnticheatConfig) -> BotScore {
+    let stats = &player.input_stats;
+    let mut bot_score = BotScore::default();
+
+    if let Some(variation) = stats.timing_variation() {
+        if variation < config.min_timing_variation {
+            bot_score.score += 1;
+            bot_score
+                .reasons
+                .push(format!("regular input timing ({:.3})", variation));
+        }
+    }
+
+    if stats.inputs as usize >= MIN_SAMPLES {
+        let on_target_ratio = stats.on_target as f32 / stats.inputs as f32;
+        if on_target_ratio > config.max_on_target_ratio {
+            bot_score.score += 1;
+            bot_score
+                .reasons
+                .push(format!("optimal targeting ({:.2})", on_target_ratio));
+        }
+    }
+
+    if stats.on_target >= 5 && stats.fast_reactions * 2 > stats.on_target {
+        bot_score.score += 1;
+        bot_score.reasons.push(format!(
+            "inhuman reactions ({}/{})",
+            stats.fast_reactions, stats.on_target
+        ));
+    }
+
+    if player.millis_alive() > config.max_session.as_millis() as u64 {
+        bot_score.score += 1;
+        bot_score
+            .reasons
+            .push(format!("session of {}s", player.millis_alive() / 1000));
+    }
+
+    bot_score
+}
+
+pub fn flagged(player: &PlayerEntity, config: &AnticheatConfig) -> Option<BotScore> {
+    if !config.enabled {
+        return None;
+    }
+
+    let bot_score = assess(player, config);
+
+    if bot_score.score >= config.flag_score {
+        Some(bot_score)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn config() -> AnticheatConfig {
+        AnticheatConfig {
+            enabled: true,
+            min_timing_variation: 0.05,
+            max_on_target_ratio: 0.9,
+            min_reaction_ticks: 2,
+            max_session: Duration::from_secs(30 * 60),
+            flag_score: 2,
+        }
+    }
+
+    async fn player() -> PlayerEntity {
+        PlayerEntity::new(Uuid::new_v4(), "player".to_string(), None, None, None).await
+    }
+
+    fn bolt(tick: u64, x: f32) -> ObjectPos {
+        ObjectPos {
+            tick,
+            pos: Vec3::new(x, 0.0, 0.0),
+        }
+    }
+
+    #[tokio::test]
+    async fn regular_instant_aim_is_flagged() {
+        let mut player = player().await;
+
+        // an input every 3 ticks, each one on a bolt the tick it appears
+        for i in 1..=30 {
+            let tick = i * 3;
+            let bolts = [bolt(tick, 10.0)];
+            player
+                .input_stats
+                .record(tick, Vec2::new(10.0, 0.0), &bolts, 2);
+        }
+
+        let bot_score = assess(&player, &config());
+        assert_eq!(bot_score.score, 3, "{:?}", bot_score.reasons);
+        assert!(flagged(&player, &config()).is_some());
+    }
+
+    #[tokio::test]
+    async fn varied_inputs_are_not_flagged() {
+        let mut player = player().await;
+        let mut tick = 0;
+
+        for i in 0..30 {
+            tick += [2, 7, 3, 11, 5][i % 5];
+            let bolts = [bolt(tick, 10.0)];
+            player
+                .input_stats
+                .record(tick, Vec2::new(-50.0, 0.0), &bolts, 2);
+        }
+
+        assert_eq!(assess(&player, &config()).score, 0);
+        assert!(flagged(&player, &config()).is_none());
+    }
+
+    #[tokio::test]
+    async fn too_few_inputs_are_not_judged() {
+        let mut player = player().await;
+
+        // perfectly regular and always on target, but under the sample minimum and slow to react
+        for i in 1..MIN_SAMPLES as u64 {
+            let tick = i * 3 + 10;
+            let bolts = [bolt(tick - 10, 10.0)];
+            player
+                .input_stats
+                .record(tick, Vec2::new(10.0, 0.0), &bolts, 2);
+        }
+
+        assert_eq!(assess(&player, &config()).score, 0);
+    }
+
+    #[tokio::test]
+    async fn flagging_needs_the_flag_score() {
+        let mut player = player().await;
+
+        // a session past the limit is one reason on its own
+        player.ticks_alive = 10 * 60 * 60;
+        assert_eq!(assess(&player, &config()).score, 1);
+        assert!(flagged(&player, &config()).is_none());
+
+        let strict = AnticheatConfig {
+            flag_score: 1,
+            ..config()
+        };
+        assert!(flagged(&player, &strict).is_some());
+
+        let disabled = AnticheatConfig {
+            enabled: false,
+            ..strict
+        };
+        assert!(flagged(&player, &disabled).is_none());
+    }
+}
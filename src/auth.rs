@@ -0,0 +1,233 @@
+use std::{
+    env,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier};
+use log::{info, warn};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::{admin::with_admin, Server};
+
+// the subprotocol entry a browser uses to carry its token, e.g. "token.<token>"
+pub const TOKEN_PROTOCOL_PREFIX: &str = "token.";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionClaims {
+    // account id from whoever issued the token
+    pub subject: String,
+    pub ln_address: Option<String>,
+    // unix seconds
+    pub expires: u64,
+}
+
+pub struct AuthConfig {
+    // None lets any origin in
+    pub allowed_origins: Option<Vec<String>>,
+    pub require_token: bool,
+    signing_key: SigningKey,
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+impl AuthConfig {
+    // SESSION_TOKEN_KEY is the hex encoded 32 byte ed25519 secret key shared with whatever
+    // issues session tokens
+    pub fn from_env() -> Self {
+        let allowed_origins = env::var("ALLOWED_ORIGINS").ok().map(|origins| {
+            origins
+                .split(',')
+                .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect()
+        });
+
+        let require_token = env::var("REQUIRE_SESSION_TOKEN").as_deref() == Ok("true");
+
+        let secret = env::var("SESSION_TOKEN_KEY")
+            .ok()
+            .and_then(|key| hex::decode(key.trim()).ok())
+            .and_then(|key| <[u8; 32]>::try_from(key).ok());
+
+        let secret = match secret {
+            Some(secret) => secret,
+            None => {
+                warn!("No SESSION_TOKEN_KEY, session tokens signed with a throwaway key");
+                rand::thread_rng().gen()
+            }
+        };
+
+        Self {
+            allowed_origins,
+            require_token,
+            signing_key: SigningKey::from_bytes(&secret),
+        }
+    }
+
+    // the allow-list controls which sites can embed the game. Browsers always send an Origin
+    // with an upgrade, so a request without one is deliberately let through: it comes from a
+    // native client or script, which could send any Origin it liked anyway. Keeping those out
+    // is what REQUIRE_SESSION_TOKEN is for
+    pub fn origin_allowed(&self, origin: Option<&str>) -> bool {
+        match (&self.allowed_origins, origin) {
+            (Some(allowed), Some(origin)) => allowed
+                .iter()
+                .any(|allowed| allowed == origin.trim_end_matches('/')),
+            (Some(_), None) | (None, _) => true,
+        }
+    }
+
+    // tokens are "<hex json claims>.<hex signature>"
+    pub fn issue(&self, claims: &SessionClaims) -> Option<String> {
+        let payload = serde_json::to_vec(claims).ok()?;
+        let signature = self.signing_key.sign(&payload).to_bytes();
+
+        Some(format!(
+            "{}.{}",
+            hex::encode(&payload),
+            hex::encode(signature)
+        ))
+    }
+
+    pub fn verify(&self, token: &str) -> Result<SessionClaims, String> {
+        let (payload, signature) = token
+            .trim()
+            .split_once('.')
+            .ok_or_else(|| "malformed token".to_string())?;
+
+        let payload = hex::decode(payload).map_err(|e| format!("invalid payload: {}", e))?;
+        let signature = hex::decode(signature)
+            .ok()
+            .and_then(|signature| <[u8; 64]>::try_from(signature).ok())
+            .ok_or_else(|| "invalid signature".to_string())?;
+
+        self.signing_key
+            .verifying_key()
+            .verify(&payload, &Signature::from_bytes(&signature))
+            .map_err(|_| "signature does not match".to_string())?;
+
+        let claims: SessionClaims =
+            serde_json::from_slice(&payload).map_err(|e| format!("invalid claims: {}", e))?;
+
+        if claims.expires <= now_secs() {
+            return Err("token expired".to_string());
+        }
+
+        Ok(claims)
+    }
+
+    // a verified session pins the LN address payouts may go to
+    pub fn payout_address_allowed(
+        &self,
+        claims: Option<&SessionClaims>,
+        ln_address: &str,
+    ) -> Result<(), &'static str> {
+        match claims.map(|claims| claims.ln_address.as_deref()) {
+            Some(Some(verified)) if !verified.eq_ignore_ascii_case(ln_address) => {
+                Err("LN address does not match your session")
+            }
+            Some(None) if self.require_token => Err("your session has no verified LN address"),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn token_from_protocols(protocols: &str) -> Option<&str> {
+    protocols
+        .split(',')
+        .map(str::trim)
+        .find(|protocol| protocol.starts_with(TOKEN_PROTOCOL_PREFIX))
+}
+
+pub struct Authorized {
+    pub claims: Option<SessionClaims>,
+    // the subprotocol to echo back, browsers drop the connection otherwise
+    pub protocol: Option<String>,
+}
+
+// checks the upgrade request before a connection slot is taken
+pub fn authorize(
+    config: &AuthConfig,
+    origin: Option<String>,
+    query_token: Option<String>,
+    protocols: Option<String>,
+) -> Result<Authorized, (StatusCode, &'static str)> {
+    if !config.origin_allowed(origin.as_deref()) {
+        warn!("Rejected upgrade from origin {:?}", origin);
+        return Err((StatusCode::FORBIDDEN, "origin not allowed"));
+    }
+
+    let protocol = protocols
+        .as_deref()
+        .and_then(token_from_protocols)
+        .map(str::to_string);
+
+    let token = query_token.or_else(|| {
+        protocol
+            .as_deref()
+            .map(|protocol| protocol[TOKEN_PROTOCOL_PREFIX.len()..].to_string())
+    });
+
+    let claims = match token {
+        Some(token) => match config.verify(&token) {
+            Ok(claims) => Some(claims),
+            Err(reason) => {
+                warn!("Rejected session token: {}", reason);
+                return Err((StatusCode::UNAUTHORIZED, "invalid session token"));
+            }
+        },
+        None if config.require_token => {
+            return Err((StatusCode::UNAUTHORIZED, "session token required"))
+        }
+        None => None,
+    };
+
+    Ok(Authorized { claims, protocol })
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenRequest {
+    subject: String,
+    ln_address: Option<String>,
+    ttl_secs: Option<u64>,
+}
+
+// lets an account service with the admin token mint session tokens for its users
+pub fn routes(
+    server: Arc<Server>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("admin" / "tokens")
+        .and(warp::post())
+        .and(with_admin(server))
+        .and(warp::body::json())
+        .map(|server: Arc<Server>, request: TokenRequest| {
+            let claims = SessionClaims {
+                subject: request.subject,
+                ln_address: request.ln_address,
+                expires: now_secs().saturating_add(request.ttl_secs.unwrap_or(24 * 60 * 60)),
+            };
+
+            match server.auth.issue(&claims) {
+                Some(token) => {
+                    info!("Issued session token for {}", claims.subject);
+                    warp::reply::with_status(
+                        warp::reply::json(&json!({ "token": token, "expires": claims.expires })),
+                        StatusCode::OK,
+                    )
+                }
+                None => warp::reply::with_status(
+                    warp::reply::json(&json!({ "error": "failed to issue token" })),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                ),
+            }
+        })
+}
@@ -0,0 +1,301 @@
+use std::{collections::HashSet, net::IpAddr, sync::Arc};
+
+use log::{error, info};
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::{
+    admin::with_admin,
+    auth::now_secs,
+    game_loop::PlayerEntity,
+    leaderboard, lifecycle,
+    messages::{DisconnectReason, ErrorCode, NetworkMessage},
+    names::{anonymous_name, fold},
+    seasons, Server,
+};
+
+const BANS_KEY: &str = "bans";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BanKind {
+    Name,
+    LnAddress,
+    Ip,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub kind: BanKind,
+    // normalized, see `normalize`
+    pub value: String,
+    pub reason: String,
+    pub created: u64,
+    // unix seconds, None bans for good
+    pub expires: Option<u64>,
+}
+
+impl Ban {
+    fn key(&self) -> String {
+        ban_key(self.kind, &self.value)
+    }
+
+    fn expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+fn ban_key(kind: BanKind, value: &str) -> String {
+    format!("{:?}:{}", kind, value)
+}
+
+// names are folded so a ban can't be dodged with "b0b" or "B.o.b"
+pub fn normalize(kind: BanKind, value: &str) -> Option<String> {
+    match kind {
+        BanKind::Name => Some(fold(value)).filter(|name| !name.is_empty()),
+        BanKind::LnAddress => Some(value.trim().to_lowercase()).filter(|value| !value.is_empty()),
+        BanKind::Ip => value.trim().parse::<IpAddr>().ok().map(|ip| ip.to_string()),
+    }
+}
+
+pub async fn load_bans(server: &Server) {
+    let now = now_secs();
+
+    let bans: Vec<Ban> = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => redis_client
+            .hvals::<_, Vec<String>>(BANS_KEY)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|json| serde_json::from_str(json).ok())
+            .collect(),
+        None => return,
+    };
+
+    let mut active = server.bans.lock().await;
+
+    for ban in bans {
+        if ban.expired(now) {
+            remove_persisted(server, &ban.key()).await;
+        } else {
+            active.insert(ban.key(), ban);
+        }
+    }
+
+    info!("Loaded {} bans", active.len());
+}
+
+async fn remove_persisted(server: &Server, key: &str) {
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        let _: () = redis_client
+            .hdel(BANS_KEY, key)
+            .unwrap_or_else(|e| error!("Failed to remove ban {}: {}", key, e));
+    }
+}
+
+pub async fn check(server: &Server, kind: BanKind, value: &str) -> Option<Ban> {
+    let key = ban_key(kind, &normalize(kind, value)?);
+    let mut bans = server.bans.lock().await;
+
+    match bans.get(&key) {
+        Some(ban) if ban.expired(now_secs()) => {
+            info!("Ban on {} expired", key);
+            bans.remove(&key);
+            None
+        }
+        ban => ban.cloned(),
+    }
+}
+
+pub async fn profile_ban(
+    server: &Server,
+    display_name: &str,
+    ln_address: Option<&str>,
+) -> Option<Ban> {
+    if let Some(ban) = check(server, BanKind::Name, display_name).await {
+        return Some(ban);
+    }
+
+    match ln_address {
+        Some(ln_address) => check(server, BanKind::LnAddress, ln_address).await,
+        None => None,
+    }
+}
+
+pub async fn player_ban(server: &Server, player: &PlayerEntity) -> Option<Ban> {
+    if let Some(ban) = profile_ban(server, &player.name, player.ln_address.as_deref()).await {
+        return Some(ban);
+    }
+
+    match player.ip {
+        Some(ip) => check(server, BanKind::Ip, &ip.to_string()).await,
+        None => None,
+    }
+}
+
+pub fn ban_messages(ban: &Ban) -> [NetworkMessage; 2] {
+    [
+        NetworkMessage::Error {
+            code: ErrorCode::Banned,
+            message: ban.reason.clone(),
+        },
+        NetworkMessage::Disconnect(DisconnectReason::Banned),
+    ]
+}
+
+pub async fn kick(server: &Server, client_id: Uuid, ban: &Ban) {
+    info!("Kicking banned player {}: {}", client_id, ban.reason);
+
+    if let Some(connection) = server.connections.read().await.get(&client_id) {
+        let [error, _] = ban_messages(ban);
+        if let Err(e) = connection.send(error) {
+            error!("Failed to send message over WebSocket: {}", e);
+        }
+    }
+
+    lifecycle::kick(server, client_id, DisconnectReason::Banned).await;
+}
+
+// leaderboard entries go with the ban. An address isn't on the public records, so its entries
+// are the ones set by runs that paid out to it this season, plus its anonymous name
+async fn purge_leaderboard(server: &Server, ban: &Ban) {
+    match ban.kind {
+        BanKind::Name => {
+            leaderboard::remove_folded(server, "ban", &HashSet::from([ban.value.clone()])).await;
+        }
+        BanKind::LnAddress => {
+            let anonymous = fold(&anonymous_name(&ban.value));
+            let player_ids = seasons::players_paid_to(server, &ban.value).await;
+
+            leaderboard::remove_where(server, "ban", |record| {
+                fold(&record.name) == anonymous || player_ids.contains(&record.player_id)
+            })
+            .await;
+        }
+        BanKind::Ip => {}
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BanRequest {
+    kind: BanKind,
+    value: String,
+    reason: String,
+    duration_secs: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnbanRequest {
+    kind: BanKind,
+    value: String,
+}
+
+pub fn routes(
+    server: Arc<Server>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let admin = with_admin(server);
+
+    let list = warp::path!("admin" / "bans")
+        .and(warp::get())
+        .and(admin.clone())
+        .and_then(list_bans);
+
+    let add = warp::path!("admin" / "bans")
+        .and(warp::post())
+        .and(admin.clone())
+        .and(warp::body::json())
+        .and_then(add_ban);
+
+    let remove = warp::path!("admin" / "bans")
+        .and(warp::delete())
+        .and(admin)
+        .and(warp::body::json())
+        .and_then(remove_ban);
+
+    list.or(add).or(remove)
+}
+
+async fn list_bans(server: Arc<Server>) -> Result<impl Reply, Rejection> {
+    let now = now_secs();
+    let mut bans: Vec<Ban> = server
+        .bans
+        .lock()
+        .await
+        .values()
+        .filter(|ban| !ban.expired(now))
+        .cloned()
+        .collect();
+    bans.sort_by_key(|ban| ban.created);
+
+    Ok(warp::reply::json(&bans))
+}
+
+async fn add_ban(server: Arc<Server>, request: BanRequest) -> Result<impl Reply, Rejection> {
+    let value = match normalize(request.kind, &request.value) {
+        Some(value) => value,
+        None => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({ "error": "invalid value" })),
+                StatusCode::BAD_REQUEST,
+            ))
+        }
+    };
+
+    let created = now_secs();
+    let ban = Ban {
+        kind: request.kind,
+        value,
+        reason: request.reason,
+        created,
+        expires: request
+            .duration_secs
+            .map(|secs| created.saturating_add(secs)),
+    };
+
+    info!("Banned {}: {}", ban.key(), ban.reason);
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        match serde_json::to_string(&ban) {
+            Ok(json) => {
+                let _: () = redis_client
+                    .hset(BANS_KEY, ban.key(), json)
+                    .unwrap_or_else(|e| error!("Failed to store ban: {}", e));
+            }
+            Err(e) => error!("Failed to serialize ban: {}", e),
+        }
+    }
+
+    server.bans.lock().await.insert(ban.key(), ban.clone());
+    purge_leaderboard(&server, &ban).await;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&ban),
+        StatusCode::OK,
+    ))
+}
+
+async fn remove_ban(server: Arc<Server>, request: UnbanRequest) -> Result<impl Reply, Rejection> {
+    let key = normalize(request.kind, &request.value).map(|value| ban_key(request.kind, &value));
+
+    let removed = match &key {
+        Some(key) => server.bans.lock().await.remove(key),
+        None => None,
+    };
+
+    match (key, removed) {
+        (Some(key), Some(_)) => {
+            info!("Unbanned {}", key);
+            remove_persisted(&server, &key).await;
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({ "status": "removed" })),
+                StatusCode::OK,
+            ))
+        }
+        _ => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({ "error": "unknown ban" })),
+            StatusCode::NOT_FOUND,
+        )),
+    }
+}
@@ -0,0 +1,228 @@
+use std::{env, net::IpAddr, time::Duration};
+
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use tokio::time::Instant;
+
+use crate::{messages::Challenge, Server};
+
+#[derive(Debug, Clone)]
+pub struct ChallengeConfig {
+    pub enabled: bool,
+    pub base_difficulty: u8,
+    pub max_difficulty: u8,
+    pub volume_step_msats: u64,
+    pub volume_window: Duration,
+    pub ttl: Duration,
+}
+
+impl ChallengeConfig {
+    pub fn from_env() -> Self {
+        let enabled = env::var("POW_CHALLENGE").as_deref() == Ok("true");
+
+        let base_difficulty = env::var("POW_DIFFICULTY")
+            .ok()
+            .and_then(|difficulty| difficulty.parse().ok())
+            .unwrap_or(16);
+
+        let max_difficulty = env::var("POW_MAX_DIFFICULTY")
+            .ok()
+            .and_then(|difficulty| difficulty.parse().ok())
+            .unwrap_or(24);
+
+        let volume_step_msats = env::var("POW_VOLUME_STEP_MSATS")
+            .ok()
+            .and_then(|step| step.parse().ok())
+            .unwrap_or(100_000);
+
+        let volume_window = env::var("POW_VOLUME_WINDOW_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(24 * 60 * 60));
+
+        let ttl = env::var("POW_CHALLENGE_TTL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(120));
+
+        Self {
+            enabled,
+            base_difficulty,
+            max_difficulty,
+            volume_step_msats,
+            volume_window,
+            ttl,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PayoutVolume {
+    pub msats: u64,
+    pub window_start: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingChallenge {
+    pub challenge: Challenge,
+    pub display_name: String,
+    // None for a player building up a balance to claim over LNURL-withdraw
+    pub ln_address: Option<String>,
+    pub issued: Instant,
+}
+
+fn volume_keys(ln_address: Option<&str>, ip: Option<IpAddr>) -> Vec<String> {
+    let mut keys = Vec::new();
+
+    if let Some(ln_address) = ln_address {
+        keys.push(format!("address:{}", ln_address));
+    }
+
+    if let Some(ip) = ip {
+        keys.push(format!("ip:{}", ip));
+    }
+
+    keys
+}
+
+pub async fn record_volume(
+    server: &Server,
+    ln_address: Option<&str>,
+    ip: Option<IpAddr>,
+    msats: u64,
+) {
+    let mut payout_volume = server.payout_volume.lock().await;
+    let window = server.challenge.volume_window;
+
+    payout_volume.retain(|_, volume| volume.window_start.elapsed() < window);
+
+    for key in volume_keys(ln_address, ip) {
+        payout_volume
+            .entry(key)
+            .or_insert_with(|| PayoutVolume {
+                msats: 0,
+                window_start: Instant::now(),
+            })
+            .msats += msats;
+    }
+}
+
+// one extra bit of work for every step of recent payouts to the address or ip
+pub async fn difficulty_for(server: &Server, ln_address: Option<&str>, ip: Option<IpAddr>) -> u8 {
+    let config = &server.challenge;
+    let payout_volume = server.payout_volume.lock().await;
+
+    let volume = volume_keys(ln_address, ip)
+        .iter()
+        .filter_map(|key| payout_volume.get(key))
+        .filter(|volume| volume.window_start.elapsed() < config.volume_window)
+        .map(|volume| volume.msats)
+        .max()
+        .unwrap_or(0);
+
+    let extra = (volume / config.volume_step_msats.max(1)).min(u8::MAX as u64) as u8;
+
+    config
+        .base_difficulty
+        .saturating_add(extra)
+        .min(config.max_difficulty)
+}
+
+pub fn new_challenge(difficulty: u8) -> Challenge {
+    let prefix: [u8; 16] = rand::thread_rng().gen();
+    Challenge::new(hex::encode(prefix), difficulty)
+}
+
+// sha256("<prefix>:<nonce>") has to start with `difficulty` zero bits
+pub fn verify(challenge: &Challenge, nonce: u64) -> bool {
+    let hash = Sha256::digest(format!("{}:{}", challenge.prefix, nonce).as_bytes());
+    leading_zero_bits(&hash) >= challenge.difficulty as u32
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+
+    for byte in hash {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            bits += byte.leading_zeros();
+            break;
+        }
+    }
+
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(challenge: &Challenge) -> u64 {
+        (0..).find(|nonce| verify(challenge, *nonce)).unwrap()
+    }
+
+    #[test]
+    fn solutions_are_checked_against_the_difficulty() {
+        let challenge = Challenge::new("00112233445566778899aabbccddeeff".to_string(), 8);
+        let nonce = solve(&challenge);
+        assert!(verify(&challenge, nonce));
+
+        // the same nonce only has as many zero bits as its hash happens to have
+        let hash = Sha256::digest(format!("{}:{}", challenge.prefix, nonce).as_bytes());
+        let harder = Challenge::new(challenge.prefix.clone(), leading_zero_bits(&hash) as u8 + 1);
+        assert!(!verify(&harder, nonce));
+
+        assert!(verify(&Challenge::new(challenge.prefix.clone(), 0), 0));
+    }
+
+    #[tokio::test]
+    async fn difficulty_rises_with_payout_volume() {
+        let server = Server {
+            challenge: ChallengeConfig {
+                enabled: true,
+                base_difficulty: 16,
+                max_difficulty: 20,
+                volume_step_msats: 100_000,
+                volume_window: Duration::from_secs(60 * 60),
+                ttl: Duration::from_secs(120),
+            },
+            ..Server::default()
+        };
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+
+        assert_eq!(
+            difficulty_for(&server, Some("a@example.com"), Some(ip)).await,
+            16
+        );
+
+        record_volume(&server, Some("a@example.com"), None, 250_000).await;
+        assert_eq!(
+            difficulty_for(&server, Some("a@example.com"), None).await,
+            18
+        );
+        assert_eq!(
+            difficulty_for(&server, Some("b@example.com"), None).await,
+            16
+        );
+
+        // volume is tracked per ip as well, the higher of the two counts
+        record_volume(&server, None, Some(ip), 350_000).await;
+        assert_eq!(
+            difficulty_for(&server, Some("a@example.com"), Some(ip)).await,
+            19
+        );
+        assert_eq!(
+            difficulty_for(&server, Some("b@example.com"), Some(ip)).await,
+            19
+        );
+
+        record_volume(&server, Some("a@example.com"), None, 10_000_000).await;
+        assert_eq!(
+            difficulty_for(&server, Some("a@example.com"), None).await,
+            20
+        );
+    }
+}
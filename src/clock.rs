@@ -0,0 +1,142 @@
+use std::{
+    env,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use tokio::{
+    sync::Mutex,
+    time::{Interval, MissedTickBehavior},
+};
+
+use crate::game_loop::TICK_MILLIS;
+
+// paces the simulation and stamps wall-clock times. Run durations are counted in ticks, so a
+// clock that doesn't wait gives the same times as the real one, only faster
+#[async_trait]
+pub trait Clock: Send + Sync {
+    // resolves when the next simulation tick is due
+    async fn next_tick(&self);
+    // only for timestamps like `set_at`, never for measuring a run
+    fn unix_secs(&self) -> u64;
+}
+
+pub fn ticks_to_millis(ticks: u64) -> u64 {
+    ticks * TICK_MILLIS
+}
+
+#[derive(Default)]
+pub struct SystemClock {
+    // created on the first tick, an interval needs a running runtime
+    interval: Mutex<Option<Interval>>,
+}
+
+#[async_trait]
+impl Clock for SystemClock {
+    async fn next_tick(&self) {
+        let mut interval = self.interval.lock().await;
+
+        let interval = interval.get_or_insert_with(|| {
+            let mut interval = tokio::time::interval(Duration::from_millis(TICK_MILLIS));
+            // a late tick pushes the schedule back instead of bursting to catch up
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+
+        interval.tick().await;
+    }
+
+    fn unix_secs(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+// ticks as fast as the loop can run and reports a fixed time, for tests and replays
+pub struct ManualClock {
+    unix_secs: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(unix_secs: u64) -> Self {
+        Self {
+            unix_secs: AtomicU64::new(unix_secs),
+        }
+    }
+}
+
+#[async_trait]
+impl Clock for ManualClock {
+    async fn next_tick(&self) {
+        tokio::task::yield_now().await;
+    }
+
+    fn unix_secs(&self) -> u64 {
+        self.unix_secs.load(Ordering::Relaxed)
+    }
+}
+
+// CLOCK=manual runs the simulation unthrottled, e.g. to replay recorded inputs, with
+// CLOCK_UNIX_SECS as the time stamped on records
+pub fn from_env() -> Box<dyn Clock> {
+    match env::var("CLOCK").as_deref() {
+        Ok("manual") => {
+            let unix_secs = env::var("CLOCK_UNIX_SECS")
+                .ok()
+                .and_then(|secs| secs.parse().ok())
+                .unwrap_or(0);
+
+            Box::new(ManualClock::new(unix_secs))
+        }
+        _ => Box::new(SystemClock::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        game_loop::{simulate, PlayerEntity},
+        lifecycle::{queue_player, register},
+        messages::NetworkMessage,
+        Server,
+    };
+
+    #[tokio::test]
+    async fn run_time_depends_only_on_ticks() {
+        let server = Arc::new(Server {
+            clock: Box::new(ManualClock::new(1_700_000_000)),
+            ..Server::default()
+        });
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (client_id, _) = register(&server, tx).await;
+        let player = PlayerEntity::new(client_id, "player".to_string(), None, None, None).await;
+        queue_player(&server, player).await;
+
+        tokio::spawn(simulate(server.clone()));
+
+        // spawned on tick 1, with a game state every 10 ticks and rain still far overhead
+        let mut millis_alive = Vec::new();
+        while millis_alive.len() < 5 {
+            if let Some(NetworkMessage::GameState(players)) = rx.recv().await {
+                let state = players
+                    .iter()
+                    .find(|player| player.id == client_id)
+                    .unwrap();
+                millis_alive.push(state.millis_alive);
+            }
+        }
+
+        // the manual clock never waits, so these can only have come from counting ticks
+        assert_eq!(millis_alive, [900, 1_900, 2_900, 3_900, 4_900]);
+        assert_eq!(server.clock.unix_secs(), 1_700_000_000);
+    }
+}
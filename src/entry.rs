@@ -0,0 +1,287 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    net::IpAddr,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use log::{error, info};
+use redis::Commands;
+use tokio::{sync::mpsc::UnboundedSender, time::Instant};
+use uuid::Uuid;
+
+use crate::{
+    anticheat::flagged,
+    game_loop::PlayerEntity,
+    lifecycle::queue_player,
+    messages::{EntryInvoice, ErrorCode, NetworkMessage},
+    payments::pay_ln_address,
+    Server,
+};
+
+#[derive(Debug, Clone)]
+pub struct EntryConfig {
+    pub fee_msats: Option<u64>,
+    pub invoice_ttl: Duration,
+    pub round: Duration,
+    pub prize_split: Vec<u64>,
+}
+
+impl EntryConfig {
+    pub fn from_env() -> Self {
+        let fee_msats = env::var("ENTRY_FEE_MSATS")
+            .ok()
+            .and_then(|fee| fee.parse().ok())
+            .filter(|fee| *fee > 0);
+
+        let invoice_ttl = env::var("ENTRY_INVOICE_TTL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(600));
+
+        let round = env::var("ROUND_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(60 * 60));
+
+        // percentage of the pool for 1st, 2nd, 3rd...
+        let prize_split = match env::var("PRIZE_SPLIT") {
+            Ok(split) => parse_split(&split).unwrap_or_else(|e| {
+                error!("Invalid PRIZE_SPLIT, using {:?}: {}", DEFAULT_SPLIT, e);
+                DEFAULT_SPLIT.to_vec()
+            }),
+            Err(_) => DEFAULT_SPLIT.to_vec(),
+        };
+
+        Self {
+            fee_msats,
+            invoice_ttl,
+            round,
+            prize_split,
+        }
+    }
+}
+
+const DEFAULT_SPLIT: [u64; 3] = [50, 30, 20];
+
+// a typo would shift everyone's share and more than 100% would pay out money that isn't in
+// the pool, so either is refused
+fn parse_split(split: &str) -> Result<Vec<u64>, String> {
+    let split = split
+        .split(',')
+        .map(str::trim)
+        .filter(|share| !share.is_empty())
+        .map(|share| {
+            share
+                .parse::<u64>()
+                .map_err(|e| format!("{:?}: {}", share, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let total: u64 = split.iter().sum();
+    if total > 100 {
+        return Err(format!("shares add up to {}%", total));
+    }
+
+    Ok(split)
+}
+
+fn prize_pool_key(room: &str) -> String {
+    format!("prize_pool:{}", room)
+}
+
+// unpaid entry fees carry over between rounds, so they have to survive a restart too
+pub async fn load_prize_pool(server: &Server) {
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        let pool: u64 = redis_client
+            .get::<_, Option<u64>>(prize_pool_key(&server.room))
+            .ok()
+            .flatten()
+            .unwrap_or(0);
+
+        info!("Prize pool: {} msats", pool);
+        server.prize_pool.store(pool, Ordering::SeqCst);
+    }
+}
+
+// reads the pool under the redis lock, so whichever update is stored last stores the latest total
+async fn store_prize_pool(server: &Server) {
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        let pool = server.prize_pool.load(Ordering::SeqCst);
+
+        let _: () = redis_client
+            .set(prize_pool_key(&server.room), pool)
+            .unwrap_or_else(|e| error!("Failed to store prize pool: {}", e));
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RoundFinish {
+    pub id: Uuid,
+    pub name: String,
+    pub ln_address: String,
+    pub ticks: u64,
+}
+
+fn send_to(
+    connections: &HashMap<Uuid, UnboundedSender<NetworkMessage>>,
+    client_id: Uuid,
+    message: NetworkMessage,
+) {
+    if let Some(connection) = connections.get(&client_id) {
+        if let Err(e) = connection.send(message) {
+            error!("Failed to send message over WebSocket: {}", e);
+        }
+    }
+}
+
+// with an entry fee the player only spawns once their invoice is paid. Prizes go to LN
+// addresses, so players without one aren't asked to pay for a round they can't win
+pub async fn admit(
+    server: Arc<Server>,
+    client_id: Uuid,
+    name: String,
+    ln_address: Option<String>,
+    ip: Option<IpAddr>,
+    account: Option<String>,
+) {
+    let fee = match server.entry.fee_msats {
+        Some(fee) => fee,
+        None => {
+            let player = PlayerEntity::new(client_id, name, ln_address, ip, account).await;
+            queue_player(&server, player).await;
+            return;
+        }
+    };
+
+    if ln_address.is_none() {
+        info!("{} has no LN address to enter with", client_id);
+        let message = NetworkMessage::Error {
+            code: ErrorCode::EntryNeedsLnAddress,
+            message: "entering this room needs an LN address to pay prizes to".to_string(),
+        };
+        send_to(&*server.connections.read().await, client_id, message);
+        return;
+    }
+
+    if !server.pending_entries.lock().await.insert(client_id) {
+        return;
+    }
+
+    let invoice = match server
+        .payment_provider
+        .create_invoice(
+            fee,
+            &server.rewards.comment,
+            server.entry.invoice_ttl.as_secs() as u32,
+        )
+        .await
+    {
+        Ok(invoice) => invoice,
+        Err(e) => {
+            error!("Failed to create entry invoice for {}: {}", client_id, e);
+            server.pending_entries.lock().await.remove(&client_id);
+            return;
+        }
+    };
+
+    let entry_invoice = NetworkMessage::EntryInvoice(EntryInvoice::new(
+        invoice.request.clone(),
+        fee,
+        server.entry.invoice_ttl.as_secs(),
+    ));
+    send_to(&*server.connections.read().await, client_id, entry_invoice);
+
+    tokio::spawn(async move {
+        let created = Instant::now();
+        let mut interval = tokio::time::interval(Duration::from_secs(2));
+
+        let paid = loop {
+            interval.tick().await;
+
+            // decided before the invoice is checked, so one paid at the last moment still
+            // counts, and a fee paid after leaving still goes into the pool
+            let expired = created.elapsed() > server.entry.invoice_ttl;
+            let left = !server.connections.read().await.contains_key(&client_id);
+
+            match server.payment_provider.invoice_paid(&invoice.id).await {
+                Ok(true) => break true,
+                Ok(false) => {}
+                Err(e) => error!("Failed to check entry invoice {}: {}", invoice.id, e),
+            }
+
+            if expired {
+                info!("Entry invoice for {} expired", client_id);
+                break false;
+            }
+
+            if left {
+                break false;
+            }
+        };
+
+        server.pending_entries.lock().await.remove(&client_id);
+        if !paid {
+            return;
+        }
+
+        info!("Entry paid by {}: {} msats", client_id, fee);
+        server.prize_pool.fetch_add(fee, Ordering::SeqCst);
+        store_prize_pool(&server).await;
+
+        // only spawns if they are still connected
+        let player = PlayerEntity::new(client_id, name, ln_address, ip, account).await;
+        queue_player(&server, player).await;
+    });
+}
+
+pub async fn record_finish(server: &Server, player: &PlayerEntity) {
+    let ln_address = match (&server.entry.fee_msats, &player.ln_address) {
+        (Some(_), Some(ln_address)) => ln_address.clone(),
+        _ => return,
+    };
+
+    if flagged(player, &server.anticheat).is_some() {
+        info!(
+            "Flagged finish by {:?} not eligible for prizes",
+            player.name
+        );
+        return;
+    }
+
+    server.round_finishers.lock().await.push(RoundFinish {
+        id: player.id,
+        name: player.name.clone(),
+        ln_address,
+        ticks: player.ticks_alive,
+    });
+}
+
+// pays the fastest finishers of the round, whatever isn't won stays in the pool
+pub async fn end_round(server: &Arc<Server>) {
+    let mut finishers = std::mem::take(&mut *server.round_finishers.lock().await);
+    finishers.sort_by_key(|finish| finish.ticks);
+
+    let mut winners = HashSet::new();
+    finishers.retain(|finish| winners.insert(finish.ln_address.clone()));
+
+    let pool = server.prize_pool.load(Ordering::SeqCst);
+    let mut paid = 0;
+
+    for (finish, share) in finishers.iter().zip(server.entry.prize_split.iter()) {
+        let amount = pool * share / 100;
+        info!("Round prize for {:?}: {} msats", finish.name, amount);
+        pay_ln_address(server.clone(), finish.id, finish.ln_address.clone(), amount).await;
+        paid += amount;
+    }
+
+    let _ = server
+        .prize_pool
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pool| {
+            Some(pool.saturating_sub(paid))
+        });
+    store_prize_pool(server).await;
+}
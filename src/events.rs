@@ -0,0 +1,234 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    env, future,
+    sync::{atomic::Ordering, Arc},
+};
+
+use futures_util::{stream, StreamExt};
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use warp::{http::StatusCode, reply::Response, sse, Filter, Rejection, Reply};
+
+use crate::{messages::HighScore, Server};
+
+// events a slow subscriber can fall behind by before it starts missing some
+pub const BUFFER: usize = 256;
+
+const KINDS: &[&str] = &["high_scores", "finish", "death", "payout", "players"];
+
+#[derive(Debug, Clone)]
+pub struct EventConfig {
+    // open /events streams, separate from the game's connection limits
+    pub max_subscribers: usize,
+    // sites allowed to read the stream from a browser, none unless EVENTS_ALLOWED_ORIGINS is set
+    pub allowed_origins: HashSet<String>,
+}
+
+impl EventConfig {
+    pub fn from_env() -> Self {
+        let max_subscribers = env::var("MAX_EVENT_SUBSCRIBERS")
+            .ok()
+            .and_then(|max| max.parse().ok())
+            .unwrap_or(100);
+
+        let allowed_origins = env::var("EVENTS_ALLOWED_ORIGINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|origin| origin.trim().trim_end_matches('/').to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+
+        Self {
+            max_subscribers,
+            allowed_origins,
+        }
+    }
+}
+
+// held by the stream, dropping it when the subscriber goes away frees the slot
+struct SubscriberPermit {
+    server: Arc<Server>,
+}
+
+impl SubscriberPermit {
+    fn acquire(server: Arc<Server>) -> Option<Self> {
+        let subscribers = &server.event_subscribers;
+
+        if subscribers.fetch_add(1, Ordering::Relaxed) >= server.event_config.max_subscribers {
+            subscribers.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+
+        Some(Self { server })
+    }
+}
+
+impl Drop for SubscriberPermit {
+    fn drop(&mut self) {
+        self.server
+            .event_subscribers
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// public activity for websites, overlays and bots, nothing here identifies a payout address
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    HighScores {
+        entries: Vec<HighScore>,
+    },
+    Finish {
+        name: String,
+        millis: u64,
+        rank: Option<u64>,
+        personal_best: bool,
+    },
+    Death {
+        name: String,
+        millis: u64,
+        bolts: usize,
+    },
+    Payout {
+        amount: u64,
+    },
+    Players {
+        online: usize,
+    },
+}
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::HighScores { .. } => "high_scores",
+            Event::Finish { .. } => "finish",
+            Event::Death { .. } => "death",
+            Event::Payout { .. } => "payout",
+            Event::Players { .. } => "players",
+        }
+    }
+}
+
+pub fn channel() -> broadcast::Sender<Event> {
+    broadcast::channel(BUFFER).0
+}
+
+// nobody listening isn't an error, the event is just dropped
+pub fn publish(server: &Server, event: Event) {
+    let _ = server.events.send(event);
+}
+
+pub fn routes(
+    server: Arc<Server>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("events")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("origin"))
+        .and(warp::any().map(move || server.clone()))
+        .and_then(subscribe)
+}
+
+fn sse_event(event: &Event) -> Option<sse::Event> {
+    sse::Event::default()
+        .event(event.kind())
+        .json_data(event)
+        .map_err(|e| error!("Failed to serialize event: {}", e))
+        .ok()
+}
+
+fn error(message: String, status: StatusCode) -> Response {
+    warp::reply::with_status(warp::reply::json(&json!({ "error": message })), status)
+        .into_response()
+}
+
+// /events?types=finish,death only streams those types, without it everything is sent
+async fn subscribe(
+    query: HashMap<String, String>,
+    origin: Option<String>,
+    server: Arc<Server>,
+) -> Result<Response, Rejection> {
+    let types: Option<HashSet<String>> = query.get("types").map(|types| {
+        types
+            .split(',')
+            .map(|kind| kind.trim().to_string())
+            .filter(|kind| !kind.is_empty())
+            .collect()
+    });
+
+    if let Some(unknown) = types
+        .iter()
+        .flatten()
+        .find(|kind| !KINDS.contains(&kind.as_str()))
+    {
+        return Ok(error(
+            format!(
+                "unknown event type {:?}, expected {}",
+                unknown,
+                KINDS.join(", ")
+            ),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let Some(permit) = SubscriberPermit::acquire(server.clone()) else {
+        warn!("Too many event subscribers, turning one away");
+        return Ok(error(
+            "too many subscribers".to_string(),
+            StatusCode::SERVICE_UNAVAILABLE,
+        ));
+    };
+
+    // subscribed before the current state is read so nothing in between is missed
+    let receiver = server.events.subscribe();
+
+    let current = vec![
+        Event::Players {
+            online: server.connections.read().await.len(),
+        },
+        Event::HighScores {
+            entries: server.high_scores.read().await.clone(),
+        },
+    ];
+
+    let updates = stream::unfold((receiver, permit), |(mut receiver, permit)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, (receiver, permit))),
+                Err(RecvError::Lagged(skipped)) => {
+                    info!("Event subscriber fell behind, skipped {}", skipped)
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let events = stream::iter(current)
+        .chain(updates)
+        .filter(move |event| {
+            future::ready(
+                types
+                    .as_ref()
+                    .is_none_or(|types| types.contains(event.kind())),
+            )
+        })
+        .filter_map(|event| future::ready(sse_event(&event).map(Ok::<_, Infallible>)));
+
+    let mut response = sse::reply(sse::keep_alive().stream(events)).into_response();
+
+    // only listed sites get the stream in a browser, the origin is echoed back rather than *
+    if let Some(origin) =
+        origin.filter(|origin| server.event_config.allowed_origins.contains(origin))
+    {
+        if let Ok(origin) = origin.parse() {
+            let headers = response.headers_mut();
+            headers.insert("access-control-allow-origin", origin);
+            headers.insert("vary", warp::http::HeaderValue::from_static("origin"));
+        }
+    }
+
+    Ok(response)
+}
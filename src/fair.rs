@@ -0,0 +1,521 @@
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+
+use log::{error, info};
+use rand::Rng;
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::{messages::SeedUpdate, Server};
+
+const MAX_HISTORY: usize = 1000;
+const BLOCK_POLL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevealedPeriod {
+    pub index: u64,
+    pub room: String,
+    pub start_tick: u64,
+    pub end_tick: u64,
+    pub commitment: String,
+    pub server_seed: String,
+    pub entropy: String,
+    pub sim_seed: u64,
+    // false when the commitment wasn't published before the period started
+    #[serde(default)]
+    pub precommitted: bool,
+    // the block whose hash is the entropy, none for the first period
+    #[serde(default)]
+    pub entropy_height: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeedPeriod {
+    pub index: u64,
+    pub start_tick: u64,
+    pub server_seed: [u8; 32],
+    pub commitment: String,
+    pub entropy: String,
+    pub sim_seed: u64,
+    pub precommitted: bool,
+    #[serde(default)]
+    pub entropy_height: Option<u64>,
+}
+
+// what has to survive a restart for the published commitments to be kept, the seeds are secret
+// until revealed so this only ever lives in redis
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredSeeds {
+    current: SeedPeriod,
+    next_seed: [u8; 32],
+    #[serde(default)]
+    next_entropy_height: Option<u64>,
+    pending_entropy: Option<String>,
+}
+
+// the server seed for each period is committed to before the period starts and only revealed
+// once it is over. the simulation runs from the server seed mixed with the hash of a bitcoin
+// block that is picked only after the commitment has been published, so neither the server nor
+// its admins know the entropy while the seed can still be changed. A period runs on past its
+// end until that block has been mined and fetched. If it hasn't arrived `entropy_timeout_ticks`
+// later the next period starts with empty entropy, on its committed seed alone, and its
+// revealed round shows as much
+#[derive(Debug)]
+pub struct FairState {
+    pub room: String,
+    pub period_ticks: u64,
+    pub entropy_timeout_ticks: u64,
+    // an esplora api such as mempool.space's, the only place entropy comes from
+    pub block_api: String,
+    pub current: SeedPeriod,
+    pub next_seed: [u8; 32],
+    pub next_commitment: String,
+    pub next_entropy_height: Option<u64>,
+    pub pending_entropy: Option<String>,
+    pub history: Vec<RevealedPeriod>,
+}
+
+pub fn commitment(server_seed: &[u8]) -> String {
+    hex::encode(Sha256::digest(server_seed))
+}
+
+pub fn sim_seed(server_seed: &[u8], entropy: &str) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update(server_seed);
+    hasher.update(entropy.as_bytes());
+    let hash = hasher.finalize();
+
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn seeds_key(room: &str) -> String {
+    format!("fair_seeds:{}", room)
+}
+
+impl FairState {
+    pub fn new(room: &str) -> Self {
+        let period_ticks = env::var("SEED_PERIOD_SECS")
+            .ok()
+            .and_then(|secs| secs.parse::<u64>().ok())
+            .unwrap_or(60 * 60)
+            * 10;
+
+        let entropy_timeout_ticks = env::var("ENTROPY_TIMEOUT_SECS")
+            .ok()
+            .and_then(|secs| secs.parse::<u64>().ok())
+            .unwrap_or(60 * 60)
+            * 10;
+
+        let block_api = env::var("ENTROPY_BLOCK_API")
+            .unwrap_or_else(|_| "https://mempool.space/api".to_string())
+            .trim_end_matches('/')
+            .to_string();
+
+        let server_seed: [u8; 32] = rand::thread_rng().gen();
+        // the very first period can't have been committed to in advance, it's marked as such
+        let entropy = env::var("FAIR_ENTROPY").unwrap_or_default();
+        let next_seed: [u8; 32] = rand::thread_rng().gen();
+
+        Self {
+            room: room.to_string(),
+            period_ticks,
+            entropy_timeout_ticks,
+            block_api,
+            current: SeedPeriod {
+                index: 0,
+                start_tick: 0,
+                server_seed,
+                commitment: commitment(&server_seed),
+                sim_seed: sim_seed(&server_seed, &entropy),
+                entropy,
+                precommitted: false,
+                entropy_height: None,
+            },
+            next_seed,
+            next_commitment: commitment(&next_seed),
+            next_entropy_height: None,
+            pending_entropy: None,
+            history: Vec::new(),
+        }
+    }
+
+    // in stream mode the seed never leaves the server, only the commitment does
+    pub fn seed_update(&self, reveal_seed: bool) -> SeedUpdate {
+        SeedUpdate::new(
+            reveal_seed.then_some(self.current.sim_seed),
+            self.current.start_tick,
+            self.current.commitment.clone(),
+            self.current.entropy.clone(),
+            self.next_commitment.clone(),
+        )
+    }
+
+    pub fn end_tick(&self) -> u64 {
+        self.current.start_tick + self.period_ticks
+    }
+
+    // past this the next period starts without waiting for its entropy any longer
+    pub fn entropy_deadline(&self) -> u64 {
+        self.end_tick().saturating_add(self.entropy_timeout_ticks)
+    }
+
+    // a period runs on past its end until the entropy for the next one arrives
+    pub fn period_over(&self, tick: u64) -> bool {
+        tick >= self.end_tick()
+            && (self.pending_entropy.is_some() || tick >= self.entropy_deadline())
+    }
+
+    // reveals the current period and starts the one that was committed to
+    pub fn rotate(&mut self, tick: u64) -> RevealedPeriod {
+        let revealed = RevealedPeriod {
+            index: self.current.index,
+            room: self.room.clone(),
+            start_tick: self.current.start_tick,
+            end_tick: tick,
+            commitment: self.current.commitment.clone(),
+            server_seed: hex::encode(self.current.server_seed),
+            entropy: self.current.entropy.clone(),
+            sim_seed: self.current.sim_seed,
+            precommitted: self.current.precommitted,
+            entropy_height: self.current.entropy_height,
+        };
+
+        let entropy = self.pending_entropy.take().unwrap_or_else(|| {
+            error!(
+                "No entropy from block {:?}, period {} runs on its committed seed alone",
+                self.next_entropy_height,
+                self.current.index + 1
+            );
+            String::new()
+        });
+
+        self.current = SeedPeriod {
+            index: self.current.index + 1,
+            start_tick: tick,
+            server_seed: self.next_seed,
+            commitment: self.next_commitment.clone(),
+            sim_seed: sim_seed(&self.next_seed, &entropy),
+            entropy,
+            precommitted: true,
+            entropy_height: self.next_entropy_height.take(),
+        };
+
+        self.next_seed = rand::thread_rng().gen();
+        self.next_commitment = commitment(&self.next_seed);
+
+        self.history.push(revealed.clone());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+
+        revealed
+    }
+
+    fn stored(&self) -> StoredSeeds {
+        StoredSeeds {
+            current: self.current.clone(),
+            next_seed: self.next_seed,
+            next_entropy_height: self.next_entropy_height,
+            pending_entropy: self.pending_entropy.clone(),
+        }
+    }
+
+    // the period that was running keeps going, ticks count from zero again after a restart
+    fn restore(&mut self, stored: StoredSeeds) {
+        self.current = SeedPeriod {
+            start_tick: 0,
+            ..stored.current
+        };
+        self.next_seed = stored.next_seed;
+        self.next_commitment = commitment(&stored.next_seed);
+        self.next_entropy_height = stored.next_entropy_height;
+        self.pending_entropy = stored.pending_entropy;
+    }
+}
+
+async fn store_seeds(server: &Server) {
+    let (key, stored) = {
+        let fair = server.fair.lock().await;
+        (seeds_key(&fair.room), serde_json::to_string(&fair.stored()))
+    };
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        match stored {
+            Ok(json) => {
+                let _: () = redis_client
+                    .set(key, json)
+                    .unwrap_or_else(|e| error!("Failed to store seeds: {}", e));
+            }
+            Err(e) => error!("Failed to serialize seeds: {}", e),
+        }
+    }
+}
+
+pub async fn rotate_seed(server: &Server, tick: u64) -> u64 {
+    let (revealed, rng_seed) = {
+        let mut fair = server.fair.lock().await;
+        let revealed = fair.rotate(tick);
+        (revealed, fair.current.sim_seed)
+    };
+
+    info!(
+        "Revealed seed period {} ({})",
+        revealed.index, revealed.server_seed
+    );
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        match serde_json::to_string(&revealed) {
+            Ok(json) => {
+                let _: () = redis_client
+                    .rpush(format!("fair_rounds:{}", revealed.room), json)
+                    .unwrap_or_else(|e| error!("Failed to store revealed seed: {}", e));
+            }
+            Err(e) => error!("Failed to serialize revealed seed: {}", e),
+        }
+    }
+
+    store_seeds(server).await;
+
+    rng_seed
+}
+
+// loads the revealed periods and picks up the seeds committed to before a restart
+pub async fn load_history(server: &Server) {
+    {
+        let mut fair = server.fair.lock().await;
+        let key = format!("fair_rounds:{}", fair.room);
+
+        let mut redis = server.redis.lock().await;
+        let Some(redis_client) = redis.as_mut() else {
+            return;
+        };
+
+        let history: Vec<String> = redis_client
+            .lrange(&key, -(MAX_HISTORY as isize), -1)
+            .unwrap_or_default();
+
+        fair.history = history
+            .iter()
+            .filter_map(|json| serde_json::from_str(json).ok())
+            .collect();
+
+        let stored: Option<StoredSeeds> = redis_client
+            .get::<_, Option<String>>(seeds_key(&fair.room))
+            .ok()
+            .flatten()
+            .and_then(|json| serde_json::from_str(&json).ok());
+
+        match stored {
+            Some(stored) => {
+                info!("Resuming seed period {}", stored.current.index);
+                fair.restore(stored);
+            }
+            // keep counting periods on from the last revealed one
+            None => {
+                if let Some(last) = fair.history.last() {
+                    fair.current.index = last.index + 1;
+                }
+            }
+        }
+
+        server
+            .seed
+            .store(fair.current.sim_seed, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    store_seeds(server).await;
+}
+
+async fn fetch_text(url: &str) -> Result<String, reqwest::Error> {
+    let text = reqwest::get(url).await?.error_for_status()?.text().await?;
+    Ok(text.trim().to_string())
+}
+
+// picks the block whose hash becomes the next period's entropy, one past the tip seen after the
+// next commitment was published, then fetches its hash once it has been mined. Anything fetched
+// is only kept if the commitment it was fetched for is still the next one
+pub async fn watch_entropy(server: Arc<Server>) {
+    let mut interval = tokio::time::interval(BLOCK_POLL);
+
+    loop {
+        interval.tick().await;
+
+        let (block_api, next_commitment, height) = {
+            let fair = server.fair.lock().await;
+            if fair.pending_entropy.is_some() {
+                continue;
+            }
+            (
+                fair.block_api.clone(),
+                fair.next_commitment.clone(),
+                fair.next_entropy_height,
+            )
+        };
+
+        let Some(height) = height else {
+            let tip = match fetch_text(&format!("{}/blocks/tip/height", block_api)).await {
+                Ok(tip) => tip.parse::<u64>().ok(),
+                Err(e) => {
+                    error!("Failed to fetch the chain tip: {}", e);
+                    continue;
+                }
+            };
+
+            let Some(tip) = tip else {
+                error!("Chain tip from {} is not a height", block_api);
+                continue;
+            };
+
+            {
+                let mut fair = server.fair.lock().await;
+                if fair.next_commitment != next_commitment || fair.next_entropy_height.is_some() {
+                    continue;
+                }
+
+                info!("Entropy for next seed period from block {}", tip + 1);
+                fair.next_entropy_height = Some(tip + 1);
+            }
+
+            store_seeds(&server).await;
+            continue;
+        };
+
+        let hash = match fetch_text(&format!("{}/block-height/{}", block_api, height)).await {
+            Ok(hash) => hash,
+            // not mined yet
+            Err(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => continue,
+            Err(e) => {
+                error!("Failed to fetch block {}: {}", height, e);
+                continue;
+            }
+        };
+
+        if hash.len() != 64 || hex::decode(&hash).is_err() {
+            error!("Block {} from {} is not a block hash", height, block_api);
+            continue;
+        }
+
+        {
+            let mut fair = server.fair.lock().await;
+            if fair.next_commitment != next_commitment || fair.pending_entropy.is_some() {
+                continue;
+            }
+
+            info!("Entropy for next seed period: {} (block {})", hash, height);
+            fair.pending_entropy = Some(hash);
+        }
+
+        store_seeds(&server).await;
+    }
+}
+
+pub fn verify(server_seed: &str, entropy: &str, expected_commitment: &str) -> Result<u64, String> {
+    let server_seed =
+        hex::decode(server_seed).map_err(|e| format!("invalid server seed: {}", e))?;
+
+    if commitment(&server_seed) != expected_commitment.to_lowercase() {
+        return Err("server seed does not match commitment".to_string());
+    }
+
+    Ok(sim_seed(&server_seed, entropy))
+}
+
+pub fn routes(
+    server: Arc<Server>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let with_server = warp::any().map(move || server.clone());
+
+    let current = warp::path!("fair" / "current")
+        .and(warp::get())
+        .and(with_server.clone())
+        .and_then(current_period);
+
+    let rounds = warp::path!("fair" / "rounds")
+        .and(warp::get())
+        .and(with_server.clone())
+        .and_then(list_rounds);
+
+    let round = warp::path!("fair" / "rounds" / u64)
+        .and(warp::get())
+        .and(with_server)
+        .and_then(get_round);
+
+    let verify = warp::path!("fair" / "verify")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(verify_round);
+
+    current.or(rounds).or(round).or(verify)
+}
+
+async fn current_period(server: Arc<Server>) -> Result<impl Reply, Rejection> {
+    let fair = server.fair.lock().await;
+
+    Ok(warp::reply::json(&json!({
+        "room": fair.room,
+        "index": fair.current.index,
+        "start_tick": fair.current.start_tick,
+        "end_tick": fair.end_tick(),
+        "commitment": fair.current.commitment,
+        "entropy": fair.current.entropy,
+        "entropy_height": fair.current.entropy_height,
+        "precommitted": fair.current.precommitted,
+        "next_commitment": fair.next_commitment,
+        "next_entropy_height": fair.next_entropy_height,
+        "next_entropy": fair.pending_entropy,
+        "entropy_deadline_tick": fair.entropy_deadline(),
+    })))
+}
+
+async fn list_rounds(server: Arc<Server>) -> Result<impl Reply, Rejection> {
+    let fair = server.fair.lock().await;
+    Ok(warp::reply::json(&fair.history))
+}
+
+async fn get_round(index: u64, server: Arc<Server>) -> Result<impl Reply, Rejection> {
+    let fair = server.fair.lock().await;
+
+    match fair.history.iter().find(|period| period.index == index) {
+        Some(period) => Ok(warp::reply::with_status(
+            warp::reply::json(period),
+            StatusCode::OK,
+        )),
+        None => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({ "error": "round not revealed" })),
+            StatusCode::NOT_FOUND,
+        )),
+    }
+}
+
+fn verify_round(query: HashMap<String, String>) -> impl Reply {
+    let (server_seed, entropy, commitment) = match (
+        query.get("server_seed"),
+        query.get("entropy"),
+        query.get("commitment"),
+    ) {
+        (Some(server_seed), Some(entropy), Some(commitment)) => (server_seed, entropy, commitment),
+        _ => {
+            return warp::reply::with_status(
+                warp::reply::json(
+                    &json!({ "error": "server_seed, entropy and commitment required" }),
+                ),
+                StatusCode::BAD_REQUEST,
+            )
+        }
+    };
+
+    match verify(server_seed, entropy, commitment) {
+        Ok(sim_seed) => warp::reply::with_status(
+            warp::reply::json(&json!({ "valid": true, "sim_seed": sim_seed })),
+            StatusCode::OK,
+        ),
+        Err(reason) => warp::reply::with_status(
+            warp::reply::json(&json!({ "valid": false, "reason": reason })),
+            StatusCode::OK,
+        ),
+    }
+}
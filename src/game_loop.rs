@@ -1,6 +1,7 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     env,
+    net::IpAddr,
     sync::Arc,
 };
 
@@ -8,19 +9,39 @@ use glam::{Vec2, Vec3};
 use log::{error, info};
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
-use redis::{Commands, RedisError};
 use tokio::time::Instant;
 use uuid::Uuid;
-use zebedee_rust::ln_address::LnPayment;
 
-pub const TICK_RATE: f32 = 1. / 10.;
+pub const TICK_MILLIS: u64 = 100;
 pub const X_BOUNDS: f32 = 1000.0;
 pub const Y_BOUNDS: f32 = 500.0;
 pub const PLAYER_SPEED: f32 = 2.5;
 pub const FALL_SPEED: f32 = 3.0;
+pub const FINISH_SCORE: usize = 21;
+// ticks of positions kept for rewinding late inputs
+pub const MAX_INPUT_HISTORY: u64 = 100;
 
 use crate::{
-    messages::{Damage, NetworkMessage, NewPos, ObjectMsg, PlayerState, Score},
+    anticheat::{flagged, InputStats},
+    bans::{self, load_bans, player_ban},
+    clock::ticks_to_millis,
+    entry::{end_round, load_prize_pool, record_finish},
+    events::{self, Event},
+    fair::{load_history, rotate_seed, watch_entropy},
+    leaderboard, lifecycle,
+    lnurl::create_claim,
+    login::load_accounts,
+    messages::{
+        Damage, NetworkMessage, NewPos, ObjectMsg, PaymentStatus, PlayerState, RunRank, RunResult,
+        RunSummary, Score,
+    },
+    payments::{
+        credit, end_run_payments, load_carry_over, load_reviews, resolve_payments, settle,
+        settlement_due,
+    },
+    results::store_result,
+    seasons::{self, load_season},
+    spawns::SpawnMode,
     Server,
 };
 
@@ -33,27 +54,63 @@ pub struct PlayerEntity {
     pub name: String,
     pub pos: Vec3,
     pub target: Vec2,
-    pub spawn_time: Instant,
     pub score: usize,
     pub alive: bool,
-    pub ln_address: bool,
+    // verified payout address, never sent to other clients
+    pub ln_address: Option<String>,
     pub prev_pos: HashMap<u64, Vec3>,
+    pub earned: u64,
+    pub credited: u64,
+    pub claimable: u64,
+    pub last_settlement: Instant,
+    pub ip: Option<IpAddr>,
+    pub input_stats: InputStats,
+    pub spawn_tick: u64,
+    // ticks simulated since `spawn_tick`, what every run time is measured in
+    pub ticks_alive: u64,
+    // verified account from the session token, if the player connected with one
+    pub account: Option<String>,
+    // set once the run summary is out, a run only ends once
+    pub run_ended: bool,
 }
 
 impl PlayerEntity {
-    pub async fn new(id: Uuid, name: String, ln_address: bool) -> Self {
+    pub async fn new(
+        id: Uuid,
+        name: String,
+        ln_address: Option<String>,
+        ip: Option<IpAddr>,
+        account: Option<String>,
+    ) -> Self {
         Self {
             id,
             name,
             pos: Vec3::new(0.0, 0.0, 0.0),
             target: Vec2::new(0.0, 0.0),
-            spawn_time: Instant::now(),
             score: 0,
             alive: true,
             ln_address,
             prev_pos: HashMap::new(),
+            earned: 0,
+            credited: 0,
+            claimable: 0,
+            last_settlement: Instant::now(),
+            ip,
+            input_stats: InputStats::default(),
+            spawn_tick: 0,
+            ticks_alive: 0,
+            account,
+            run_ended: false,
         }
     }
+    pub fn millis_alive(&self) -> u64 {
+        ticks_to_millis(self.ticks_alive)
+    }
+    pub fn record_position(&mut self, tick: u64) {
+        self.prev_pos.insert(tick, self.pos);
+        self.prev_pos
+            .retain(|prev_tick, _| *prev_tick + MAX_INPUT_HISTORY > tick);
+    }
     pub async fn apply_input(&mut self) {
         let movement = self.calculate_movement().await;
 
@@ -86,6 +143,7 @@ pub struct Objects {
     pub rain_pos: Vec<ObjectPos>,
     pub bolt_pos: Vec<ObjectPos>,
     pub rng_seed: u64,
+    pub upcoming: VecDeque<(u64, f32)>,
 }
 
 pub struct ObjectPos {
@@ -93,12 +151,20 @@ pub struct ObjectPos {
     pub pos: Vec3,
 }
 
+pub fn spawn_x(rng_seed: u64, tick: u64) -> f32 {
+    let seed = rng_seed ^ tick;
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+    rng.gen_range(-X_BOUNDS..X_BOUNDS)
+}
+
 impl Objects {
     pub async fn new(rng_seed: u64) -> Self {
         Self {
             rain_pos: Vec::new(),
             bolt_pos: Vec::new(),
             rng_seed,
+            upcoming: VecDeque::new(),
         }
     }
     pub async fn update_global_objects(&mut self, server: Arc<Server>) {
@@ -117,14 +183,63 @@ impl Objects {
         let objects = ObjectMsg::new(rain_with_ticks, bolt_with_ticks);
 
         server.objects.lock().await.replace(objects);
+
+        if server.spawns.mode == SpawnMode::Stream {
+            let upcoming = self.upcoming_spawns(self.upcoming.iter().copied());
+            server.upcoming_spawns.lock().await.replace(upcoming);
+        }
     }
-    pub async fn move_rain(&mut self, tick: u64) {
-        let seed = self.rng_seed ^ tick;
-        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    fn upcoming_spawns(&self, spawns: impl Iterator<Item = (u64, f32)>) -> ObjectMsg {
+        let mut rain_pos = Vec::new();
+        let mut bolt_pos = Vec::new();
+
+        for (tick, x) in spawns {
+            if tick % 5 == 0 {
+                bolt_pos.push((tick, [x, Y_BOUNDS]));
+            } else {
+                rain_pos.push((tick, [x, Y_BOUNDS]));
+            }
+        }
+
+        ObjectMsg::new(rain_pos, bolt_pos)
+    }
+    // decides the spawn `lookahead` ticks from now with the current seed and returns it so it
+    // can be announced before it appears
+    pub async fn queue_spawn(&mut self, tick: u64, lookahead: u64) -> ObjectMsg {
+        let spawn_tick = tick + lookahead;
+        let spawn = (spawn_tick, spawn_x(self.rng_seed, spawn_tick));
+        self.upcoming.push_back(spawn);
+
+        self.upcoming_spawns(std::iter::once(spawn))
+    }
+    // spawns queued under the old seed are redrawn from the new one and returned so they can be
+    // announced again, the new announcement replaces the old one for the same ticks
+    pub fn reseed(&mut self, rng_seed: u64) -> ObjectMsg {
+        self.rng_seed = rng_seed;
 
-        let x_position: f32 = rng.gen_range(-X_BOUNDS..X_BOUNDS);
+        for (spawn_tick, x) in self.upcoming.iter_mut() {
+            *x = spawn_x(rng_seed, *spawn_tick);
+        }
 
+        self.upcoming_spawns(self.upcoming.iter().copied())
+    }
+    fn next_spawn_x(&mut self, tick: u64) -> f32 {
+        while let Some((spawn_tick, x)) = self.upcoming.front().copied() {
+            self.upcoming.pop_front();
+            if spawn_tick == tick {
+                return x;
+            }
+            if spawn_tick > tick {
+                self.upcoming.push_front((spawn_tick, x));
+                break;
+            }
+        }
+
+        spawn_x(self.rng_seed, tick)
+    }
+    pub async fn move_rain(&mut self, tick: u64) {
         if tick % 5 != 0 {
+            let x_position = self.next_spawn_x(tick);
             let pos_start = Vec3::new(x_position, Y_BOUNDS, 0.0);
             let new_pos = ObjectPos {
                 tick,
@@ -145,12 +260,8 @@ impl Objects {
         });
     }
     pub async fn move_bolts(&mut self, tick: u64) {
-        let seed = self.rng_seed ^ tick;
-        let mut rng = ChaCha8Rng::seed_from_u64(seed);
-
-        let x_position: f32 = rng.gen_range(-X_BOUNDS..X_BOUNDS);
-
         if tick % 5 == 0 {
+            let x_position = self.next_spawn_x(tick);
             let pos_start = Vec3::new(x_position, Y_BOUNDS, 0.0);
             let new_pos = ObjectPos {
                 tick,
@@ -171,7 +282,6 @@ impl Objects {
         });
     }
     pub async fn collision(&mut self, players: &mut Players, server: Arc<Server>) {
-        let server_clone = server.clone();
         for player in &mut players.0 {
             for i in (0..self.rain_pos.len()).rev() {
                 let object = &self.rain_pos[i];
@@ -188,12 +298,24 @@ impl Objects {
                         player_inputs.clear();
                     }
 
+                    settle(&server, player, true).await;
+                    run_summary(&server, player, None).await;
+
+                    events::publish(
+                        &server,
+                        Event::Death {
+                            name: player.name.clone(),
+                            millis: player.millis_alive(),
+                            bolts: player.score,
+                        },
+                    );
+
                     let highscore_msg = server.high_scores.read().await;
 
                     let damage_update_msg = NetworkMessage::DamagePlayer(Damage::new(
                         player.id,
                         Some(object_tick),
-                        player.spawn_time.elapsed().as_secs(),
+                        player.millis_alive(),
                         Some(highscore_msg.clone()),
                         [player.pos.x, player.pos.y],
                         player.score,
@@ -207,9 +329,15 @@ impl Objects {
                     }
 
                     info!("Player {:?}{:?} hit by rain", player.name, player.id,);
+                    break;
                 }
             }
 
+            // the run was settled when they died, nothing more can be earned in it
+            if !player.alive {
+                continue;
+            }
+
             for i in (0..self.bolt_pos.len()).rev() {
                 let object = &self.bolt_pos[i];
                 if (object.pos.x - player.pos.x).abs() < 10.0
@@ -222,48 +350,27 @@ impl Objects {
 
                     info!("Player {:?}{:?} hit by bolt", player.name, player.id,);
 
-                    let connections = server.connections.read().await;
+                    {
+                        let connections = server.connections.read().await;
 
-                    for (_, connection) in connections.iter() {
-                        info!("Sending score update to {:?}", player.id);
-                        let score_update_msg = NetworkMessage::ScoreUpdate(Score::new(
-                            player.id,
-                            player.score,
-                            object_tick,
-                        ));
-                        if let Err(e) = connection.send(score_update_msg.clone()) {
-                            error!("Failed to send message over WebSocket: {}", e);
+                        for (_, connection) in connections.iter() {
+                            info!("Sending score update to {:?}", player.id);
+                            let score_update_msg = NetworkMessage::ScoreUpdate(Score::new(
+                                player.id,
+                                player.score,
+                                object_tick,
+                            ));
+                            if let Err(e) = connection.send(score_update_msg.clone()) {
+                                error!("Failed to send message over WebSocket: {}", e);
+                            }
                         }
                     }
 
-                    if player.ln_address {
-                        let payment = LnPayment {
-                            ln_address: player.name.clone(),
-                            amount: String::from("1000"),
-                            comment: String::from("https://rain.run"),
-                        };
-
-                        let server_clone_2 = server_clone.clone();
-
-                        tokio::spawn(async move {
-                            let payment_response = server_clone_2
-                                .zebedee_client
-                                .lock()
-                                .await
-                                .pay_ln_address(&payment)
-                                .await;
-
-                            match payment_response {
-                                Ok(response) => info!(
-                                    "Payment sent to {:?}: {:?}",
-                                    payment.ln_address, response.data
-                                ),
-                                Err(e) => info!("Payment failed {:?}", e),
-                            }
-                        });
-                    }
+                    let amount = server.rewards.bolt_reward(player.score)
+                        + server.rewards.milestone_reward(player.score);
+                    credit(&server, player, amount).await;
 
-                    if player.score == 21 {
+                    if player.score == FINISH_SCORE {
                         player.alive = false;
                         let mut inputs = server.player_inputs.lock().await;
 
@@ -271,74 +378,31 @@ impl Objects {
                             player_inputs.clear();
                         }
 
-                        if player.ln_address {
-                            let payment = LnPayment {
-                                ln_address: player.name.clone(),
-                                amount: String::from("21000"),
-                                comment: String::from("https://rain.run"),
-                            };
-
-                            let server_clone_2 = server_clone.clone();
-
-                            tokio::spawn(async move {
-                                let payment_response = server_clone_2
-                                    .zebedee_client
-                                    .lock()
-                                    .await
-                                    .pay_ln_address(&payment)
-                                    .await;
-
-                                match payment_response {
-                                    Ok(response) => info!(
-                                        "Payment sent to {:?}: {:?}",
-                                        payment.ln_address, response.data
-                                    ),
-                                    Err(e) => info!("Payment failed {:?}", e),
-                                }
-                            });
-                        }
+                        let amount = server.rewards.finish_reward(player.millis_alive());
+                        credit(&server, player, amount).await;
+                        settle(&server, player, true).await;
+                        record_finish(&server, player).await;
 
-                        let mut high_scores: Vec<(String, u64)> = Vec::new();
-
-                        if let Some(redis_client) = server.redis.lock().await.as_mut() {
-                            let current_score_result: Result<Option<u64>, RedisError> =
-                                redis_client.zscore("high_scores", &player.name);
-                            match current_score_result {
-                                Ok(current_score) => {
-                                    if current_score.map_or(true, |cs| {
-                                        cs > player.spawn_time.elapsed().as_secs()
-                                    }) {
-                                        let _: () = redis_client
-                                            .zadd(
-                                                "high_scores",
-                                                &player.name,
-                                                player.spawn_time.elapsed().as_secs(),
-                                            )
-                                            .unwrap_or_else(|_| {
-                                                error!("Failed to add to high_scores");
-                                            });
-                                    }
-
-                                    high_scores = redis_client
-                                        .zrange_withscores("high_scores", 0, 4)
-                                        .unwrap_or(Vec::new());
-
-                                    {
-                                        let mut high_scores_update =
-                                            server.high_scores.write().await;
-                                        *high_scores_update = high_scores;
-                                    }
-                                }
-                                Err(e) => error!("Failed to get current score: {}", e),
-                            }
-                        }
+                        let rank = leaderboard::submit_finish(&server, player).await;
+
+                        events::publish(
+                            &server,
+                            Event::Finish {
+                                name: player.name.clone(),
+                                millis: player.millis_alive(),
+                                rank: rank.as_ref().map(|rank| rank.rank),
+                                personal_best: rank.as_ref().is_some_and(|rank| rank.personal_best),
+                            },
+                        );
+
+                        run_summary(&server, player, rank).await;
 
                         let highscore_msg = server.high_scores.read().await;
 
                         let damage_update_msg = NetworkMessage::DamagePlayer(Damage::new(
                             player.id,
                             Some(object_tick),
-                            player.spawn_time.elapsed().as_secs(),
+                            player.millis_alive(),
                             Some(highscore_msg.clone()),
                             [player.pos.x, player.pos.y],
                             player.score,
@@ -350,6 +414,8 @@ impl Objects {
                                 error!("Failed to send message over WebSocket: {}", e);
                             }
                         }
+
+                        break;
                     }
                 }
             }
@@ -357,11 +423,88 @@ impl Objects {
     }
 }
 
-pub async fn game_loop(server: Arc<Server>) {
-    let mut high_scores: Vec<(String, u64)> = Vec::new();
-    let mut players = Players(Vec::new());
-    let mut objects = Objects::new(server.seed.load(std::sync::atomic::Ordering::SeqCst)).await;
+// sent only to the player whose run ended, with every payout made during the run and the
+// signed result of the run. It goes out once the run's payments have resolved, so it doesn't
+// hold up the tick
+async fn run_summary(server: &Arc<Server>, player: &mut PlayerEntity, rank: Option<RunRank>) {
+    if player.run_ended {
+        return;
+    }
+    player.run_ended = true;
+
+    let payments = end_run_payments(server, player.id).await;
 
+    // an anonymous player can't be paid later, so flagged runs get no claim at all
+    let claimable = std::mem::take(&mut player.claimable);
+    let claim = if claimable > 0 && flagged(player, &server.anticheat).is_none() {
+        create_claim(server, claimable).await
+    } else {
+        None
+    };
+
+    let (seed, seed_commitment) = {
+        let fair = server.fair.lock().await;
+        let seed = server.spawns.reveal_seed().then_some(fair.current.sim_seed);
+        (seed, fair.current.commitment.clone())
+    };
+
+    let run = RunResult {
+        id: Uuid::new_v4(),
+        player_id: player.id,
+        name: player.name.clone(),
+        account: player.account.clone(),
+        room: server.room.clone(),
+        seed,
+        seed_commitment,
+        start_tick: player.spawn_tick,
+        end_tick: server.tick.load(std::sync::atomic::Ordering::SeqCst),
+        bolts: player.score,
+        millis: player.millis_alive(),
+        // only what actually reached the player, filled in once the payments have resolved
+        msats_paid: 0,
+        finished: player.score >= FINISH_SCORE,
+    };
+
+    let server = server.clone();
+
+    tokio::spawn(async move {
+        let payments = resolve_payments(&server, payments).await;
+        let run = RunResult {
+            msats_paid: payments
+                .iter()
+                .filter(|payment| payment.status == PaymentStatus::Sent)
+                .map(|payment| payment.amount)
+                .sum(),
+            ..run
+        };
+        let result = server.result_signer.sign(run.clone());
+
+        if let Some(result) = &result {
+            info!("Run result for {:?}: {}", run.name, result.proof);
+            store_result(&server, result).await;
+        }
+
+        let summary = NetworkMessage::RunSummary(Box::new(RunSummary::new(
+            run.player_id,
+            run.bolts,
+            run.millis,
+            payments,
+            claim,
+            result,
+            rank,
+        )));
+
+        let connections = server.connections.read().await;
+
+        if let Some(connection) = connections.get(&run.player_id) {
+            if let Err(e) = connection.send(summary) {
+                error!("Failed to send message over WebSocket: {}", e);
+            }
+        }
+    });
+}
+
+pub async fn game_loop(server: Arc<Server>) {
     let redis_connect = redis();
 
     {
@@ -369,25 +512,38 @@ pub async fn game_loop(server: Arc<Server>) {
         *redis = redis_connect;
     }
 
-    if let Some(redis_client) = server.redis.lock().await.as_mut() {
-        match redis_client.zrange_withscores("high_scores", 0, 4) {
-            Ok(scores) => high_scores = scores,
-            Err(err) => error!("Failed to fetch high scores: {:?}", err),
-        }
-    } else {
-        error!("Redis client not initialized");
-    }
+    load_season(&server).await;
+    leaderboard::load(&server).await;
+    load_reviews(&server).await;
+    load_carry_over(&server).await;
+    load_prize_pool(&server).await;
+    load_history(&server).await;
+    load_accounts(&server).await;
+    load_bans(&server).await;
 
-    {
-        let mut high_scores_update = server.high_scores.write().await;
-        *high_scores_update = high_scores;
-        info!("High scores: {:?}", high_scores_update);
+    tokio::spawn(watch_entropy(server.clone()));
+
+    simulate(server).await;
+}
+
+// the simulation itself, paced by the server's clock. Run after load_history, which may resume
+// a seed committed to before a restart
+pub async fn simulate(server: Arc<Server>) {
+    let mut players = Players(Vec::new());
+    let mut objects = Objects::new(server.seed.load(std::sync::atomic::Ordering::SeqCst)).await;
+
+    if server.spawns.mode == SpawnMode::Stream {
+        for tick in 1..=server.spawns.lookahead {
+            objects.queue_spawn(tick, 0).await;
+        }
     }
 
     let mut server_tick = 0;
+    let mut round_start = Instant::now();
+    let mut online = 0;
 
     loop {
-        tokio::time::sleep(std::time::Duration::from_secs_f32(TICK_RATE)).await;
+        server.clock.next_tick().await;
         server
             .tick
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
@@ -395,27 +551,35 @@ pub async fn game_loop(server: Arc<Server>) {
 
         players.0.retain(|player| player.alive);
 
-        let mut new_player = server.player_names.lock().await;
+        // released before the rest of the tick, teardown takes this lock too
+        {
+            let mut new_player = server.player_names.lock().await;
 
-        let mut player_added = Vec::new();
+            let mut player_added = Vec::new();
 
-        for (id, player_entity) in new_player.iter() {
-            let mut inputs = server.player_inputs.lock().await;
+            for (id, player_entity) in new_player.iter() {
+                let mut inputs = server.player_inputs.lock().await;
 
-            if let Some(player_inputs) = inputs.get_mut(id) {
-                player_inputs.clear();
+                if let Some(player_inputs) = inputs.get_mut(id) {
+                    player_inputs.clear();
+                }
+                server.run_payments.lock().await.insert(*id, Vec::new());
+                let mut player_entity = player_entity.clone();
+                player_entity.spawn_tick = server_tick;
+                players.0.push(player_entity);
+                player_added.push(*id);
             }
-            players.0.push(player_entity.clone());
-            player_added.push(*id);
-        }
 
-        for id in player_added {
-            new_player.remove(&id);
+            for id in player_added {
+                new_player.remove(&id);
+            }
         }
 
         let mut updated_players: HashSet<Uuid> = HashSet::new();
 
         for player in &mut players.0 {
+            player.ticks_alive = server_tick - player.spawn_tick;
+
             let mut inputs = server.player_inputs.lock().await;
 
             // input stuff
@@ -425,12 +589,24 @@ pub async fn game_loop(server: Arc<Server>) {
                     if input_tick == server_tick {
                         let input = player_inputs[i].target;
                         player.target = Vec2::new(input[0], input[1]);
+                        player.input_stats.record(
+                            input_tick,
+                            player.target,
+                            &objects.bolt_pos,
+                            server.anticheat.min_reaction_ticks,
+                        );
                         player_inputs.remove(i);
                         updated_players.insert(player.id);
                     }
                     if input_tick < server_tick {
                         let input = player_inputs[i].target;
                         player.target = Vec2::new(input[0], input[1]);
+                        player.input_stats.record(
+                            input_tick,
+                            player.target,
+                            &objects.bolt_pos,
+                            server.anticheat.min_reaction_ticks,
+                        );
                         if let Some(pos) = player.prev_pos.get(&input_tick) {
                             player.pos = *pos;
                         }
@@ -448,7 +624,7 @@ pub async fn game_loop(server: Arc<Server>) {
 
                 player.apply_input().await;
 
-                player.prev_pos.insert(server_tick, player.pos);
+                player.record_position(server_tick);
             }
         }
 
@@ -478,14 +654,89 @@ pub async fn game_loop(server: Arc<Server>) {
             }
         }
 
+        if server.fair.lock().await.period_over(server_tick) {
+            let rng_seed = rotate_seed(&server, server_tick).await;
+
+            let respawns = objects.reseed(rng_seed);
+            server
+                .seed
+                .store(rng_seed, std::sync::atomic::Ordering::SeqCst);
+
+            let seed_update = server
+                .fair
+                .lock()
+                .await
+                .seed_update(server.spawns.reveal_seed());
+
+            let connections = server.connections.read().await;
+
+            for (_, connection) in connections.iter() {
+                let mut messages = vec![NetworkMessage::SeedUpdate(seed_update.clone())];
+                if server.spawns.mode == SpawnMode::Stream {
+                    messages.push(NetworkMessage::ObjectSpawns(respawns.clone()));
+                }
+
+                for message in messages {
+                    if let Err(e) = connection.send(message) {
+                        error!("Failed to send message over WebSocket: {}", e);
+                    }
+                }
+            }
+        }
+
+        if server.spawns.mode == SpawnMode::Stream {
+            let spawns = objects
+                .queue_spawn(server_tick, server.spawns.lookahead)
+                .await;
+
+            let connections = server.connections.read().await;
+
+            for (_, connection) in connections.iter() {
+                let message = NetworkMessage::ObjectSpawns(spawns.clone());
+
+                if let Err(e) = connection.send(message) {
+                    error!("Failed to send message over WebSocket: {}", e);
+                }
+            }
+        }
+
         objects.move_rain(server_tick).await;
         objects.move_bolts(server_tick).await;
         objects.collision(&mut players, server.clone()).await;
         objects.update_global_objects(server.clone()).await;
 
+        if server.entry.fee_msats.is_some() && round_start.elapsed() >= server.entry.round {
+            end_round(&server).await;
+            round_start = Instant::now();
+        }
+
         if server_tick % 10 == 0 {
-            let connections = server.connections.read().await;
-            let connection_ids: HashSet<_> = connections.iter().map(|(id, _)| *id).collect();
+            seasons::check(&server).await;
+
+            for player in &players.0 {
+                if let Some(ban) = player_ban(&server, player).await {
+                    bans::kick(&server, player.id, &ban).await;
+                }
+            }
+
+            let mut connection_ids: HashSet<_> = {
+                let connections = server.connections.read().await;
+                connections.iter().map(|(id, _)| *id).collect()
+            };
+
+            // players waiting to be resumed stay in the game until their grace period is up
+            connection_ids.extend(lifecycle::sweep(&server).await);
+
+            for player in &mut players.0 {
+                if !connection_ids.contains(&player.id) {
+                    // runs cut short by a disconnect or a kick still get a signed result
+                    settle(&server, player, true).await;
+                    run_summary(&server, player, None).await;
+                    lifecycle::teardown(&server, player.id).await;
+                } else if settlement_due(&server, player) {
+                    settle(&server, player, false).await;
+                }
+            }
 
             players
                 .0
@@ -500,13 +751,20 @@ pub async fn game_loop(server: Arc<Server>) {
                     player.score,
                     Some(player.name.clone()),
                     player.id,
-                    player.spawn_time.elapsed().as_secs(),
+                    player.millis_alive(),
                     player.alive,
                 );
 
                 player_state.push(player);
             }
 
+            let connections = server.connections.read().await;
+
+            if connections.len() != online {
+                online = connections.len();
+                events::publish(&server, Event::Players { online });
+            }
+
             for (_, connection) in connections.iter() {
                 let message = NetworkMessage::GameState(player_state.clone());
 
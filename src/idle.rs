@@ -0,0 +1,91 @@
+use std::{env, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::messages::PlayerInput;
+
+fn secs_from_env(key: &str, default: u64) -> Duration {
+    let secs = env::var(key)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(default);
+
+    Duration::from_secs(secs)
+}
+
+#[derive(Debug, Clone)]
+pub struct IdleConfig {
+    // a connection that sends nothing, not even a heartbeat, for this long is dropped
+    pub read_timeout: Duration,
+    // an in-game player whose target hasn't moved for `afk_warn` gets a warning, and is
+    // kicked at `afk_kick`
+    pub afk_warn: Duration,
+    pub afk_kick: Duration,
+}
+
+impl IdleConfig {
+    pub fn from_env() -> Self {
+        let afk_warn = secs_from_env("AFK_WARN_SECS", 60);
+        let afk_kick = secs_from_env("AFK_KICK_SECS", 75).max(afk_warn);
+
+        Self {
+            read_timeout: secs_from_env("HEARTBEAT_TIMEOUT_SECS", 15),
+            afk_warn,
+            afk_kick,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleAction {
+    // seconds left before the kick
+    Warn(u64),
+    Kick,
+}
+
+#[derive(Debug)]
+pub struct Activity {
+    in_game: bool,
+    last_target: Option<[f32; 2]>,
+    since: Instant,
+    warned: bool,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self {
+            in_game: false,
+            last_target: None,
+            since: Instant::now(),
+            warned: false,
+        }
+    }
+
+    pub fn record_input(&mut self, input: &PlayerInput) {
+        // clients keep sending the same target every tick, only a new one counts as activity
+        if !input.in_game || self.last_target != Some(input.target) {
+            self.since = Instant::now();
+            self.warned = false;
+        }
+
+        self.in_game = input.in_game;
+        self.last_target = Some(input.target);
+    }
+
+    pub fn check(&mut self, config: &IdleConfig) -> Option<IdleAction> {
+        if !self.in_game {
+            return None;
+        }
+
+        let idle = self.since.elapsed();
+
+        if idle >= config.afk_kick {
+            Some(IdleAction::Kick)
+        } else if idle >= config.afk_warn && !self.warned {
+            self.warned = true;
+            Some(IdleAction::Warn((config.afk_kick - idle).as_secs()))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,1031 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{error, info};
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use uuid::Uuid;
+
+use crate::{
+    admin::with_admin_actor,
+    anticheat::flagged,
+    events::{self, Event},
+    game_loop::{PlayerEntity, TICK_MILLIS},
+    messages::{HighScore, NetworkMessage, RunRank, TopScore},
+    names::{anonymous_name, fold, is_ln_address},
+    seasons, Server,
+};
+
+// names ranked by ticks, with the full record for each name alongside. Each season has its
+// own pair, suffixed with the season id
+const RANKS_KEY: &str = "high_score_ranks";
+const RECORDS_KEY: &str = "high_score_records";
+// the old sorted set of whole seconds, migrated once on startup
+const LEGACY_KEY: &str = "high_scores";
+const FROZEN_KEY: &str = "high_scores_frozen";
+const SNAPSHOTS_KEY: &str = "high_scores_snapshots";
+const AUDIT_KEY: &str = "leaderboard_audit";
+const TOP: isize = 5;
+const MAX_SNAPSHOTS: usize = 50;
+const MAX_AUDIT_ENTRIES: isize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct Board {
+    ranks: String,
+    records: String,
+}
+
+impl Board {
+    pub fn season(id: u64) -> Self {
+        Self {
+            ranks: format!("{}:{}", RANKS_KEY, id),
+            records: format!("{}:{}", RECORDS_KEY, id),
+        }
+    }
+
+    pub fn delete(&self, pipe: &mut redis::Pipeline) {
+        pipe.del(&self.ranks).del(&self.records);
+    }
+}
+
+pub async fn current_board(server: &Server) -> Board {
+    Board::season(server.season.read().await.id)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id: u64,
+    // snapshots from before seasons were of the board that became season 1
+    #[serde(default = "first_season")]
+    pub season: u64,
+    pub entries: Vec<HighScore>,
+}
+
+fn first_season() -> u64 {
+    1
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: u64,
+    pub actor: String,
+    pub action: String,
+    pub detail: Value,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+// fewer ticks first, then wall clock time, then whoever set it first, then the name, so equal
+// runs always come out in the same order
+pub fn ranking(a: &HighScore, b: &HighScore) -> Ordering {
+    (a.ticks, a.millis, a.set_at, &a.name).cmp(&(b.ticks, b.millis, b.set_at, &b.name))
+}
+
+// a logged-in player's record follows their account, display names are only unique among the
+// players online so anyone else is kept by name. Names can't contain a colon
+pub fn record_key(record: &HighScore) -> String {
+    match &record.account {
+        Some(account) => format!("account:{}", account),
+        None => record.name.clone(),
+    }
+}
+
+fn load_records(
+    redis_client: &mut redis::Connection,
+    board: &Board,
+    keys: &[String],
+) -> Vec<HighScore> {
+    if keys.is_empty() {
+        return Vec::new();
+    }
+
+    let records: Vec<Option<String>> = redis::cmd("HMGET")
+        .arg(&board.records)
+        .arg(keys)
+        .query(redis_client)
+        .unwrap_or_else(|e| {
+            error!("Failed to load high score records: {}", e);
+            Vec::new()
+        });
+
+    let mut records: Vec<HighScore> = records
+        .into_iter()
+        .flatten()
+        .filter_map(|json| serde_json::from_str(&json).ok())
+        .collect();
+    records.sort_by(ranking);
+    records
+}
+
+pub fn all_entries(redis_client: &mut redis::Connection, board: &Board) -> Vec<HighScore> {
+    let keys: Vec<String> = redis_client.zrange(&board.ranks, 0, -1).unwrap_or_default();
+    load_records(redis_client, board, &keys)
+}
+
+// the sorted set only knows ticks and breaks ties by key, so equal ticks are ordered with
+// `ranking` to agree with the top list
+fn rank_of(
+    redis_client: &mut redis::Connection,
+    board: &Board,
+    record: &HighScore,
+) -> redis::RedisResult<u64> {
+    let faster: u64 = redis_client.zcount(&board.ranks, "-inf", format!("({}", record.ticks))?;
+    let tied: Vec<String> = redis_client.zrangebyscore(&board.ranks, record.ticks, record.ticks)?;
+
+    let ahead = load_records(redis_client, board, &tied)
+        .iter()
+        .filter(|other| ranking(other, record) == Ordering::Less)
+        .count() as u64;
+
+    Ok(faster + ahead + 1)
+}
+
+// everything tied with the fifth place is fetched so the tie-break decides who's in
+fn top(redis_client: &mut redis::Connection, board: &Board) -> redis::RedisResult<Vec<HighScore>> {
+    let fifth: Vec<(String, u64)> =
+        redis_client.zrange_withscores(&board.ranks, TOP - 1, TOP - 1)?;
+
+    let keys: Vec<String> = match fifth.first() {
+        Some((_, ticks)) => redis_client.zrangebyscore(&board.ranks, "-inf", *ticks)?,
+        None => redis_client.zrange(&board.ranks, 0, -1)?,
+    };
+
+    let mut records = load_records(redis_client, board, &keys);
+    records.truncate(TOP as usize);
+    Ok(records)
+}
+
+fn write_record(pipe: &mut redis::Pipeline, board: &Board, record: &HighScore) {
+    match serde_json::to_string(record) {
+        Ok(json) => {
+            let key = record_key(record);
+            pipe.zadd(&board.ranks, &key, record.ticks)
+                .hset(&board.records, &key, json);
+        }
+        Err(e) => error!("Failed to serialize high score: {}", e),
+    }
+}
+
+fn remove_records(pipe: &mut redis::Pipeline, board: &Board, keys: &[String]) {
+    pipe.zrem(&board.ranks, keys).hdel(&board.records, keys);
+}
+
+// scores from before seasons become the current season's. The old set only had whole seconds
+// per name, those become records without a player or date
+fn migrate(server: &Server, redis_client: &mut redis::Connection, board: &Board) {
+    if redis_client.exists(&board.ranks).unwrap_or(true) {
+        return;
+    }
+
+    if redis_client.exists(RANKS_KEY).unwrap_or(false) {
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .rename(RANKS_KEY, &board.ranks)
+            .rename(RECORDS_KEY, &board.records);
+
+        match pipe.query::<()>(redis_client) {
+            Ok(_) => info!("Moved high scores into {}", board.ranks),
+            Err(e) => error!("Failed to move high scores into the season: {}", e),
+        }
+        return;
+    }
+
+    let legacy: Vec<(String, u64)> = redis_client
+        .zrange_withscores(LEGACY_KEY, 0, -1)
+        .unwrap_or_default();
+
+    if legacy.is_empty() {
+        return;
+    }
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+
+    for (name, secs) in &legacy {
+        // old clients sent their payout address as their name, it can't be published
+        let name = if is_ln_address(name) {
+            anonymous_name(name)
+        } else {
+            name.clone()
+        };
+
+        let record = HighScore {
+            player_id: Uuid::nil(),
+            account: None,
+            name,
+            ticks: secs * 1000 / TICK_MILLIS,
+            millis: secs * 1000,
+            set_at: 0,
+            room: server.room.clone(),
+            seed_commitment: String::new(),
+            ln_verified: false,
+        };
+        write_record(&mut pipe, board, &record);
+    }
+
+    pipe.rename(LEGACY_KEY, format!("{}_legacy", LEGACY_KEY));
+
+    match pipe.query::<()>(redis_client) {
+        Ok(_) => info!("Migrated {} legacy high scores", legacy.len()),
+        Err(e) => error!("Failed to migrate legacy high scores: {}", e),
+    }
+}
+
+// records of logged-in players used to be kept by name, they move to their account's key and
+// the better record wins if the account already has one
+fn rekey(redis_client: &mut redis::Connection, board: &Board) {
+    let members: Vec<String> = redis_client.zrange(&board.ranks, 0, -1).unwrap_or_default();
+    if members.is_empty() {
+        return;
+    }
+
+    let records: Vec<Option<String>> = redis::cmd("HMGET")
+        .arg(&board.records)
+        .arg(&members)
+        .query(redis_client)
+        .unwrap_or_default();
+
+    let mut moved: HashMap<String, HighScore> = HashMap::new();
+    let mut stale = Vec::new();
+
+    for (member, record) in members.iter().zip(records) {
+        let Some(record) = record.and_then(|json| serde_json::from_str::<HighScore>(&json).ok())
+        else {
+            continue;
+        };
+
+        let key = record_key(&record);
+        if *member == key {
+            continue;
+        }
+
+        stale.push(member.clone());
+        if moved
+            .get(&key)
+            .is_none_or(|current| ranking(&record, current) == Ordering::Less)
+        {
+            moved.insert(key, record);
+        }
+    }
+
+    if stale.is_empty() {
+        return;
+    }
+
+    let existing = load_records(
+        redis_client,
+        board,
+        &moved.keys().cloned().collect::<Vec<_>>(),
+    );
+
+    let mut pipe = redis::pipe();
+    remove_records(pipe.atomic(), board, &stale);
+    for record in moved.values() {
+        let kept = existing
+            .iter()
+            .find(|existing| record_key(existing) == record_key(record))
+            .filter(|existing| ranking(existing, record) == Ordering::Less)
+            .unwrap_or(record);
+        write_record(&mut pipe, board, kept);
+    }
+
+    match pipe.query::<()>(redis_client) {
+        Ok(_) => info!("Moved {} high scores to their accounts", stale.len()),
+        Err(e) => error!("Failed to move high scores to their accounts: {}", e),
+    }
+}
+
+// names frozen before they were folded are stored folded, or unfreezing them would miss
+fn fold_frozen(redis_client: &mut redis::Connection) -> HashSet<String> {
+    let stored: HashSet<String> = redis_client.smembers(FROZEN_KEY).unwrap_or_default();
+    let folded: HashSet<String> = stored.iter().map(|name| fold(name)).collect();
+
+    if folded != stored {
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(FROZEN_KEY);
+        for name in &folded {
+            pipe.sadd(FROZEN_KEY, name);
+        }
+
+        let _: () = pipe
+            .query(redis_client)
+            .unwrap_or_else(|e| error!("Failed to fold frozen names: {}", e));
+    }
+
+    folded
+}
+
+pub async fn load(server: &Server) {
+    let board = current_board(server).await;
+
+    let frozen: HashSet<String> = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => {
+            migrate(server, redis_client, &board);
+            rekey(redis_client, &board);
+            fold_frozen(redis_client)
+        }
+        None => {
+            error!("Redis client not initialized");
+            return;
+        }
+    };
+
+    info!("{} frozen leaderboard names", frozen.len());
+    *server.frozen_names.lock().await = frozen;
+
+    refresh(server).await;
+    info!("High scores: {:?}", server.high_scores.read().await);
+}
+
+pub async fn submit_finish(server: &Server, player: &PlayerEntity) -> Option<RunRank> {
+    let seed_commitment = server.fair.lock().await.current.commitment.clone();
+
+    let record = HighScore {
+        player_id: player.id,
+        account: player.account.clone(),
+        name: player.name.clone(),
+        ticks: player.ticks_alive,
+        millis: player.millis_alive(),
+        set_at: server.clock.unix_secs(),
+        room: server.room.clone(),
+        seed_commitment,
+        ln_verified: player.ln_address.is_some(),
+    };
+
+    if let Some(ln_address) = &player.ln_address {
+        if flagged(player, &server.anticheat).is_none() {
+            seasons::record_payout_address(server, player.id, ln_address).await;
+        }
+    }
+
+    submit(server, record).await
+}
+
+// a finish only replaces the entry if it ranks higher, frozen names keep what they have.
+// Returns where the player stands afterwards and how the run compares with their previous best
+pub async fn submit(server: &Server, record: HighScore) -> Option<RunRank> {
+    let frozen = server
+        .frozen_names
+        .lock()
+        .await
+        .contains(&fold(&record.name));
+    if frozen {
+        info!("Not updating frozen leaderboard entry {:?}", record.name);
+    }
+
+    let key = record_key(&record);
+    let was_top = top_position(server, &key).await.is_some();
+    let board = current_board(server).await;
+
+    let (previous, personal_best, rank) = {
+        let mut redis = server.redis.lock().await;
+        let redis_client = redis.as_mut()?;
+
+        let previous = load_records(redis_client, &board, std::slice::from_ref(&key))
+            .into_iter()
+            .next();
+
+        let personal_best = !frozen
+            && previous
+                .as_ref()
+                .is_none_or(|previous| ranking(&record, previous) == Ordering::Less);
+
+        if personal_best {
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            write_record(&mut pipe, &board, &record);
+
+            let _: () = pipe.query(redis_client).unwrap_or_else(|e| {
+                error!("Failed to add to high scores: {}", e);
+            });
+        }
+
+        let standing = if personal_best {
+            Some(&record)
+        } else {
+            previous.as_ref()
+        };
+
+        let rank = standing.and_then(|standing| {
+            rank_of(redis_client, &board, standing)
+                .map_err(|e| error!("Failed to rank {:?}: {}", record.name, e))
+                .ok()
+        });
+
+        (previous, personal_best, rank)
+    };
+
+    refresh(server).await;
+
+    let top = top_position(server, &key).await;
+
+    if personal_best && top.is_some() {
+        events::publish(
+            server,
+            Event::HighScores {
+                entries: server.high_scores.read().await.clone(),
+            },
+        );
+    }
+
+    if let (Some(position), false, true) = (top, was_top, personal_best) {
+        announce_top(server, position as u64 + 1, &record).await;
+    }
+
+    Some(RunRank {
+        rank: rank?,
+        previous_best_millis: previous.as_ref().map(|previous| previous.millis),
+        difference_millis: previous
+            .as_ref()
+            .map(|previous| record.millis as i64 - previous.millis as i64),
+        personal_best,
+        top: top.is_some(),
+    })
+}
+
+async fn top_position(server: &Server, key: &str) -> Option<usize> {
+    server
+        .high_scores
+        .read()
+        .await
+        .iter()
+        .position(|entry| record_key(entry) == key)
+}
+
+// everyone hears when a new name breaks into the top list
+async fn announce_top(server: &Server, rank: u64, record: &HighScore) {
+    info!("{:?} entered the top list at {}", record.name, rank);
+
+    let message = NetworkMessage::EnteredTop(TopScore::new(rank, record.clone()));
+    let connections = server.connections.read().await;
+
+    for (_, connection) in connections.iter() {
+        if let Err(e) = connection.send(message.clone()) {
+            error!("Failed to send message over WebSocket: {}", e);
+        }
+    }
+}
+
+// reloads the top five the server hands to new players and sends with damage updates
+pub async fn refresh(server: &Server) {
+    let board = current_board(server).await;
+
+    let high_scores = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => match top(redis_client, &board) {
+            Ok(high_scores) => high_scores,
+            Err(e) => {
+                error!("Failed to fetch high scores: {}", e);
+                return;
+            }
+        },
+        None => return,
+    };
+
+    *server.high_scores.write().await = high_scores;
+}
+
+// after a moderation change clients get the new top list straight away
+pub async fn publish(server: &Server) {
+    refresh(server).await;
+
+    let high_scores = server.high_scores.read().await.clone();
+    events::publish(
+        server,
+        Event::HighScores {
+            entries: high_scores.clone(),
+        },
+    );
+
+    let message = NetworkMessage::HighScores(high_scores);
+    let connections = server.connections.read().await;
+
+    for (_, connection) in connections.iter() {
+        if let Err(e) = connection.send(message.clone()) {
+            error!("Failed to send message over WebSocket: {}", e);
+        }
+    }
+}
+
+pub async fn audit(server: &Server, actor: &str, action: &str, detail: Value) {
+    info!("Leaderboard {} by {}: {}", action, actor, detail);
+
+    let entry = AuditEntry {
+        at: now_millis() / 1000,
+        actor: actor.to_string(),
+        action: action.to_string(),
+        detail,
+    };
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        match serde_json::to_string(&entry) {
+            Ok(json) => {
+                let _: () = redis_client
+                    .lpush(AUDIT_KEY, json)
+                    .and_then(|_: ()| redis_client.ltrim(AUDIT_KEY, 0, MAX_AUDIT_ENTRIES - 1))
+                    .unwrap_or_else(|e| error!("Failed to write audit entry: {}", e));
+            }
+            Err(e) => error!("Failed to serialize audit entry: {}", e),
+        }
+    }
+}
+
+// every destructive change is preceded by one of these so it can be undone
+async fn snapshot(server: &Server) -> Option<Snapshot> {
+    let season = server.season.read().await.id;
+    let mut redis = server.redis.lock().await;
+    let redis_client = redis.as_mut()?;
+
+    let snapshot = Snapshot {
+        id: now_millis(),
+        season,
+        entries: all_entries(redis_client, &Board::season(season)),
+    };
+
+    let json = match serde_json::to_string(&snapshot) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize snapshot: {}", e);
+            return None;
+        }
+    };
+
+    if let Err(e) = redis_client.hset::<_, _, _, ()>(SNAPSHOTS_KEY, snapshot.id, json) {
+        error!("Failed to store snapshot: {}", e);
+        return None;
+    }
+
+    let mut ids: Vec<u64> = redis_client.hkeys(SNAPSHOTS_KEY).unwrap_or_default();
+    if ids.len() > MAX_SNAPSHOTS {
+        ids.sort();
+        let stale = &ids[..ids.len() - MAX_SNAPSHOTS];
+        let _: () = redis_client
+            .hdel(SNAPSHOTS_KEY, stale)
+            .unwrap_or_else(|e| error!("Failed to prune snapshots: {}", e));
+    }
+
+    Some(snapshot)
+}
+
+// removes every entry whose folded name is in `folded_names`, returns how many went
+pub async fn remove_folded(server: &Server, actor: &str, folded_names: &HashSet<String>) -> usize {
+    remove_where(server, actor, |record| {
+        folded_names.contains(&fold(&record.name))
+    })
+    .await
+}
+
+// removes every entry `matches` picks out, returns how many went
+pub async fn remove_where(
+    server: &Server,
+    actor: &str,
+    matches: impl Fn(&HighScore) -> bool,
+) -> usize {
+    let board = current_board(server).await;
+
+    let matching: Vec<HighScore> = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => all_entries(redis_client, &board)
+            .into_iter()
+            .filter(|record| matches(record))
+            .collect(),
+        None => return 0,
+    };
+
+    if matching.is_empty() {
+        return 0;
+    }
+
+    snapshot(server).await;
+
+    let keys: Vec<String> = matching.iter().map(record_key).collect();
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        let mut pipe = redis::pipe();
+        remove_records(pipe.atomic(), &board, &keys);
+        let _: () = pipe
+            .query(redis_client)
+            .unwrap_or_else(|e| error!("Failed to remove high scores: {}", e));
+    }
+
+    let names: Vec<&String> = matching.iter().map(|record| &record.name).collect();
+    audit(server, actor, "remove", json!({ "names": names })).await;
+    publish(server).await;
+
+    matching.len()
+}
+
+#[derive(Debug, Deserialize)]
+struct RemoveRequest {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RenameRequest {
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct FreezeRequest {
+    name: String,
+    frozen: bool,
+}
+
+pub fn routes(
+    server: Arc<Server>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let admin = with_admin_actor(server);
+
+    let list = warp::path!("admin" / "leaderboard")
+        .and(warp::get())
+        .and(admin.clone())
+        .and_then(list_entries);
+
+    let remove = warp::path!("admin" / "leaderboard" / "remove")
+        .and(warp::post())
+        .and(admin.clone())
+        .and(warp::body::json())
+        .and_then(remove_entry);
+
+    let rename = warp::path!("admin" / "leaderboard" / "rename")
+        .and(warp::post())
+        .and(admin.clone())
+        .and(warp::body::json())
+        .and_then(rename_entry);
+
+    let freeze = warp::path!("admin" / "leaderboard" / "freeze")
+        .and(warp::post())
+        .and(admin.clone())
+        .and(warp::body::json())
+        .and_then(freeze_name);
+
+    let list_snapshots = warp::path!("admin" / "leaderboard" / "snapshots")
+        .and(warp::get())
+        .and(admin.clone())
+        .and_then(list_snapshots);
+
+    let take_snapshot = warp::path!("admin" / "leaderboard" / "snapshots")
+        .and(warp::post())
+        .and(admin.clone())
+        .and_then(take_snapshot);
+
+    let restore = warp::path!("admin" / "leaderboard" / "snapshots" / u64 / "restore")
+        .and(warp::post())
+        .and(admin.clone())
+        .and_then(restore_snapshot);
+
+    let audit_log = warp::path!("admin" / "leaderboard" / "audit")
+        .and(warp::get())
+        .and(admin)
+        .and_then(audit_log);
+
+    list.or(remove)
+        .or(rename)
+        .or(freeze)
+        .or(list_snapshots)
+        .or(take_snapshot)
+        .or(restore)
+        .or(audit_log)
+}
+
+fn reply(body: Value, status: StatusCode) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(warp::reply::json(&body), status)
+}
+
+fn no_redis() -> warp::reply::WithStatus<warp::reply::Json> {
+    reply(
+        json!({ "error": "leaderboard unavailable" }),
+        StatusCode::SERVICE_UNAVAILABLE,
+    )
+}
+
+async fn list_entries(server: Arc<Server>, _actor: String) -> Result<impl Reply, Rejection> {
+    let board = current_board(&server).await;
+
+    let entries = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => all_entries(redis_client, &board),
+        None => return Ok(no_redis()),
+    };
+
+    let frozen = server.frozen_names.lock().await;
+    let entries: Vec<Value> = entries
+        .into_iter()
+        .map(|record| {
+            let frozen = frozen.contains(&fold(&record.name));
+            let mut entry = json!(record);
+            entry["frozen"] = json!(frozen);
+            entry
+        })
+        .collect();
+
+    Ok(reply(json!(entries), StatusCode::OK))
+}
+
+// records are keyed by account where there is one, so moderation looks them up by name. An
+// exact match comes first, then one that only differs by lookalike characters
+async fn find_record(server: &Server, name: &str) -> Option<Option<HighScore>> {
+    let board = current_board(server).await;
+    let mut redis = server.redis.lock().await;
+    let redis_client = redis.as_mut()?;
+
+    let mut entries = all_entries(redis_client, &board);
+    let folded = fold(name);
+
+    let index = entries
+        .iter()
+        .position(|record| record.name == name)
+        .or_else(|| {
+            entries
+                .iter()
+                .position(|record| fold(&record.name) == folded)
+        });
+
+    Some(index.map(|index| entries.swap_remove(index)))
+}
+
+async fn remove_entry(
+    server: Arc<Server>,
+    actor: String,
+    request: RemoveRequest,
+) -> Result<impl Reply, Rejection> {
+    let record = match find_record(&server, &request.name).await {
+        Some(Some(record)) => record,
+        Some(None) => {
+            return Ok(reply(
+                json!({ "error": "unknown entry" }),
+                StatusCode::NOT_FOUND,
+            ))
+        }
+        None => return Ok(no_redis()),
+    };
+
+    snapshot(&server).await;
+    let board = current_board(&server).await;
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        let mut pipe = redis::pipe();
+        remove_records(pipe.atomic(), &board, &[record_key(&record)]);
+        let _: () = pipe
+            .query(redis_client)
+            .unwrap_or_else(|e| error!("Failed to remove high score: {}", e));
+    }
+
+    audit(
+        &server,
+        &actor,
+        "remove",
+        json!({ "names": [request.name] }),
+    )
+    .await;
+    publish(&server).await;
+
+    Ok(reply(json!({ "status": "removed" }), StatusCode::OK))
+}
+
+// the renamed entry keeps the better of the two records if the new name is already listed under
+// the same owner. A name another player's record holds can't be taken over
+async fn rename_entry(
+    server: Arc<Server>,
+    actor: String,
+    request: RenameRequest,
+) -> Result<impl Reply, Rejection> {
+    let to = match server.names.validate(&request.to) {
+        Ok(to) => to,
+        Err(reason) => return Ok(reply(json!({ "error": reason }), StatusCode::BAD_REQUEST)),
+    };
+
+    let (from, existing) = match (
+        find_record(&server, &request.from).await,
+        find_record(&server, &to).await,
+    ) {
+        (Some(Some(from)), Some(existing)) => (from, existing),
+        (Some(None), _) => {
+            return Ok(reply(
+                json!({ "error": "unknown entry" }),
+                StatusCode::NOT_FOUND,
+            ))
+        }
+        _ => return Ok(no_redis()),
+    };
+
+    let mut stale = vec![record_key(&from)];
+
+    let renamed = HighScore {
+        name: to.clone(),
+        ..from
+    };
+
+    // a lookalike of the entry's own name finds the entry itself
+    let existing = existing.filter(|existing| record_key(existing) != stale[0]);
+
+    if let Some(existing) = &existing {
+        if record_key(existing) != record_key(&renamed) {
+            return Ok(reply(
+                json!({ "error": "name is held by another entry", "name": existing.name }),
+                StatusCode::CONFLICT,
+            ));
+        }
+    }
+
+    let record = match existing {
+        Some(existing) => {
+            stale.push(record_key(&existing));
+
+            if ranking(&existing, &renamed) == Ordering::Less {
+                existing
+            } else {
+                renamed
+            }
+        }
+        None => renamed,
+    };
+
+    snapshot(&server).await;
+    let board = current_board(&server).await;
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        let mut pipe = redis::pipe();
+        remove_records(pipe.atomic(), &board, &stale);
+        write_record(&mut pipe, &board, &record);
+        let _: () = pipe
+            .query(redis_client)
+            .unwrap_or_else(|e| error!("Failed to rename high score: {}", e));
+    }
+
+    audit(
+        &server,
+        &actor,
+        "rename",
+        json!({ "from": request.from, "to": to, "ticks": record.ticks }),
+    )
+    .await;
+    publish(&server).await;
+
+    Ok(reply(
+        json!({ "status": "renamed", "name": to }),
+        StatusCode::OK,
+    ))
+}
+
+// frozen names are folded like every other moderation path, so "B.o.b" freezes "bob"
+async fn freeze_name(
+    server: Arc<Server>,
+    actor: String,
+    request: FreezeRequest,
+) -> Result<impl Reply, Rejection> {
+    let name = fold(&request.name);
+
+    if name.is_empty() {
+        return Ok(reply(
+            json!({ "error": "name required" }),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    snapshot(&server).await;
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        let persisted: redis::RedisResult<()> = if request.frozen {
+            redis_client.sadd(FROZEN_KEY, &name)
+        } else {
+            redis_client.srem(FROZEN_KEY, &name)
+        };
+
+        if let Err(e) = persisted {
+            error!("Failed to persist frozen name: {}", e);
+        }
+    }
+
+    {
+        let mut frozen = server.frozen_names.lock().await;
+        if request.frozen {
+            frozen.insert(name.clone());
+        } else {
+            frozen.remove(&name);
+        }
+    }
+
+    let action = if request.frozen { "freeze" } else { "unfreeze" };
+    audit(&server, &actor, action, json!({ "name": name })).await;
+    publish(&server).await;
+
+    Ok(reply(json!({ "status": action }), StatusCode::OK))
+}
+
+async fn list_snapshots(server: Arc<Server>, _actor: String) -> Result<impl Reply, Rejection> {
+    let snapshots: HashMap<u64, String> = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => redis_client.hgetall(SNAPSHOTS_KEY).unwrap_or_default(),
+        None => return Ok(no_redis()),
+    };
+
+    let mut snapshots: Vec<Snapshot> = snapshots
+        .values()
+        .filter_map(|json| serde_json::from_str(json).ok())
+        .collect();
+    snapshots.sort_by_key(|snapshot| snapshot.id);
+
+    Ok(reply(json!(snapshots), StatusCode::OK))
+}
+
+async fn take_snapshot(server: Arc<Server>, actor: String) -> Result<impl Reply, Rejection> {
+    match snapshot(&server).await {
+        Some(snapshot) => {
+            audit(&server, &actor, "snapshot", json!({ "id": snapshot.id })).await;
+            Ok(reply(json!(snapshot), StatusCode::OK))
+        }
+        None => Ok(no_redis()),
+    }
+}
+
+async fn restore_snapshot(
+    id: u64,
+    server: Arc<Server>,
+    actor: String,
+) -> Result<impl Reply, Rejection> {
+    let restored: Option<Snapshot> = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => redis_client
+            .hget::<_, _, Option<String>>(SNAPSHOTS_KEY, id)
+            .ok()
+            .flatten()
+            .and_then(|json| serde_json::from_str(&json).ok()),
+        None => return Ok(no_redis()),
+    };
+
+    let restored = match restored {
+        Some(restored) => restored,
+        None => {
+            return Ok(reply(
+                json!({ "error": "unknown snapshot" }),
+                StatusCode::NOT_FOUND,
+            ))
+        }
+    };
+
+    // an old season's standings would otherwise end up on the current board, those are in the
+    // season archive instead
+    let season = server.season.read().await.id;
+    if restored.season != season {
+        return Ok(reply(
+            json!({
+                "error": "snapshot is from another season",
+                "season": restored.season,
+                "current_season": season,
+            }),
+            StatusCode::CONFLICT,
+        ));
+    }
+
+    // the current state is kept too, so a restore can be undone
+    snapshot(&server).await;
+    let board = current_board(&server).await;
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        let mut pipe = redis::pipe();
+        board.delete(pipe.atomic());
+        for record in &restored.entries {
+            write_record(&mut pipe, &board, record);
+        }
+
+        let _: () = pipe
+            .query(redis_client)
+            .unwrap_or_else(|e| error!("Failed to restore snapshot {}: {}", id, e));
+    }
+
+    audit(
+        &server,
+        &actor,
+        "restore",
+        json!({ "id": id, "entries": restored.entries.len() }),
+    )
+    .await;
+    publish(&server).await;
+
+    Ok(reply(json!({ "status": "restored" }), StatusCode::OK))
+}
+
+async fn audit_log(server: Arc<Server>, _actor: String) -> Result<impl Reply, Rejection> {
+    let entries: Vec<String> = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => redis_client
+            .lrange(AUDIT_KEY, 0, MAX_AUDIT_ENTRIES - 1)
+            .unwrap_or_default(),
+        None => return Ok(no_redis()),
+    };
+
+    let entries: Vec<AuditEntry> = entries
+        .iter()
+        .filter_map(|json| serde_json::from_str(json).ok())
+        .collect();
+
+    Ok(reply(json!(entries), StatusCode::OK))
+}
@@ -0,0 +1,274 @@
+use log::info;
+use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
+
+use crate::{
+    game_loop::PlayerEntity,
+    messages::{DisconnectReason, NetworkMessage, PlayerInput},
+    names, Server,
+};
+
+// inputs queued for a client that the game loop hasn't picked up yet
+pub const MAX_PENDING_INPUTS: usize = 64;
+
+// every piece of per-client state is registered and torn down here so nothing outlives the
+// client
+
+pub async fn register(server: &Server, tx: UnboundedSender<NetworkMessage>) -> (Uuid, Uuid) {
+    let client_id = Uuid::new_v4();
+
+    server.connections.write().await.insert(client_id, tx);
+    let resume_token = server.sessions.lock().await.issue(client_id);
+
+    (client_id, resume_token)
+}
+
+// moves a resumed connection onto the client id of the player it is taking over
+pub async fn reattach(
+    server: &Server,
+    client_id: Uuid,
+    old_id: Uuid,
+    tx: UnboundedSender<NetworkMessage>,
+) {
+    {
+        let mut connections = server.connections.write().await;
+        connections.remove(&client_id);
+
+        // the old socket may still be open, e.g. the same player in a second tab
+        if let Some(replaced) = connections.insert(old_id, tx) {
+            let _ = replaced.send(NetworkMessage::Disconnect(DisconnectReason::Replaced));
+        }
+    }
+
+    server.player_inputs.lock().await.remove(&client_id);
+    server.player_names.lock().await.remove(&client_id);
+    server.sessions.lock().await.forget(client_id);
+    names::release(server, client_id).await;
+}
+
+pub async fn queue_player(server: &Server, player: PlayerEntity) {
+    if !server.connections.read().await.contains_key(&player.id) {
+        info!("{} left before spawning", player.id);
+        return;
+    }
+
+    server.player_names.lock().await.insert(player.id, player);
+}
+
+// queued, spawned or waiting on an entry payment. A spawned player keeps its run payments
+// until its run summary
+pub async fn playing(server: &Server, client_id: Uuid) -> bool {
+    server.player_names.lock().await.contains_key(&client_id)
+        || server.run_payments.lock().await.contains_key(&client_id)
+        || server.pending_entries.lock().await.contains(&client_id)
+}
+
+pub async fn push_input(server: &Server, client_id: Uuid, input: PlayerInput) {
+    let mut inputs = server.player_inputs.lock().await;
+    let player_inputs = inputs.entry(client_id).or_insert_with(Vec::new);
+
+    if player_inputs.len() >= MAX_PENDING_INPUTS {
+        player_inputs.remove(0);
+    }
+    player_inputs.push(input);
+}
+
+// drops the connection unless a resumed session has already replaced it
+async fn release(server: &Server, client_id: Uuid, tx: &UnboundedSender<NetworkMessage>) -> bool {
+    let mut connections = server.connections.write().await;
+    if !connections
+        .get(&client_id)
+        .is_some_and(|connection| connection.same_channel(tx))
+    {
+        return false;
+    }
+
+    connections.remove(&client_id);
+    true
+}
+
+// the socket is gone, but a spawned player is kept around for the resume grace period
+pub async fn disconnect(server: &Server, client_id: Uuid, tx: &UnboundedSender<NetworkMessage>) {
+    if !release(server, client_id, tx).await {
+        return;
+    }
+
+    server.player_inputs.lock().await.remove(&client_id);
+    server.player_names.lock().await.remove(&client_id);
+    server.sessions.lock().await.disconnect(client_id);
+}
+
+// kicked clients don't get a grace period, their player leaves on the next sweep
+pub async fn evict(server: &Server, client_id: Uuid, tx: &UnboundedSender<NetworkMessage>) {
+    if release(server, client_id, tx).await {
+        teardown(server, client_id).await;
+    }
+}
+
+// closes the connection with a reason and drops the client without a grace period
+pub async fn kick(server: &Server, client_id: Uuid, reason: DisconnectReason) {
+    if let Some(connection) = server.connections.write().await.remove(&client_id) {
+        let _ = connection.send(NetworkMessage::Disconnect(reason));
+    }
+
+    teardown(server, client_id).await;
+}
+
+pub async fn teardown(server: &Server, client_id: Uuid) {
+    server.connections.write().await.remove(&client_id);
+    server.player_inputs.lock().await.remove(&client_id);
+    server.player_names.lock().await.remove(&client_id);
+    server.run_payments.lock().await.remove(&client_id);
+    server.sessions.lock().await.forget(client_id);
+    names::release(server, client_id).await;
+}
+
+// tears down clients whose grace period ran out and returns the ones still waiting to resume
+pub async fn sweep(server: &Server) -> Vec<Uuid> {
+    let (expired, waiting) = {
+        let mut sessions = server.sessions.lock().await;
+        (sessions.expire(), sessions.waiting())
+    };
+
+    for client_id in expired {
+        teardown(server, client_id).await;
+    }
+
+    waiting
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{atomic::Ordering, Arc},
+        time::Duration,
+    };
+
+    use tokio::{sync::mpsc, time::timeout};
+
+    use super::*;
+    use crate::{
+        clock::ManualClock,
+        game_loop::{simulate, MAX_INPUT_HISTORY},
+    };
+
+    async fn state_sizes(server: &Server) -> [usize; 6] {
+        let sessions = server.sessions.lock().await;
+
+        [
+            server.connections.read().await.len(),
+            server.player_inputs.lock().await.len(),
+            server.player_names.lock().await.len(),
+            server.run_payments.lock().await.len(),
+            sessions.tokens.len(),
+            sessions.disconnected.len(),
+        ]
+    }
+
+    #[tokio::test]
+    async fn connect_disconnect_cycles_return_to_baseline() {
+        let server = Server::default();
+        server.sessions.lock().await.grace = Duration::ZERO;
+
+        let baseline = state_sizes(&server).await;
+
+        for cycle in 0..1000 {
+            let (tx, _rx) = mpsc::unbounded_channel();
+            let (client_id, _) = register(&server, tx.clone()).await;
+
+            for tick in 0..100 {
+                let input = PlayerInput {
+                    target: [0.0, 0.0],
+                    id: client_id,
+                    tick,
+                    in_game: true,
+                };
+                push_input(&server, client_id, input).await;
+            }
+
+            if cycle % 2 == 0 {
+                let player =
+                    PlayerEntity::new(client_id, "player".to_string(), None, None, None).await;
+                queue_player(&server, player).await;
+                server
+                    .run_payments
+                    .lock()
+                    .await
+                    .insert(client_id, Vec::new());
+            }
+
+            disconnect(&server, client_id, &tx).await;
+            sweep(&server).await;
+        }
+
+        assert_eq!(state_sizes(&server).await, baseline);
+    }
+
+    #[tokio::test]
+    async fn simulation_keeps_ticking_after_grace_expiry() {
+        let server = Arc::new(Server {
+            clock: Box::new(ManualClock::new(1_700_000_000)),
+            ..Server::default()
+        });
+        server.sessions.lock().await.grace = Duration::from_millis(10);
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (client_id, _) = register(&server, tx.clone()).await;
+        let player = PlayerEntity::new(client_id, "player".to_string(), None, None, None).await;
+        queue_player(&server, player).await;
+
+        tokio::spawn(simulate(server.clone()));
+
+        // wait for the player to spawn, then drop the socket and let the grace period run out
+        while !matches!(rx.recv().await, Some(NetworkMessage::GameState(_))) {}
+        disconnect(&server, client_id, &tx).await;
+
+        timeout(Duration::from_secs(5), async {
+            while !server.sessions.lock().await.disconnected.is_empty() {
+                tokio::task::yield_now().await;
+            }
+
+            // the expiry is torn down by the loop's sweep, which must not wedge the next ticks
+            let expired_at = server.tick.load(Ordering::SeqCst);
+            while server.tick.load(Ordering::SeqCst) < expired_at + 100 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("the game loop stopped ticking");
+
+        assert_eq!(state_sizes(&server).await, [0; 6]);
+    }
+
+    #[tokio::test]
+    async fn pending_inputs_are_bounded() {
+        let server = Server::default();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (client_id, _) = register(&server, tx).await;
+
+        for tick in 0..1000 {
+            let input = PlayerInput {
+                target: [0.0, 0.0],
+                id: client_id,
+                tick,
+                in_game: true,
+            };
+            push_input(&server, client_id, input).await;
+        }
+
+        let inputs = server.player_inputs.lock().await;
+        assert_eq!(inputs[&client_id].len(), MAX_PENDING_INPUTS);
+    }
+
+    #[tokio::test]
+    async fn position_history_is_bounded() {
+        let mut player =
+            PlayerEntity::new(Uuid::new_v4(), "player".to_string(), None, None, None).await;
+
+        for tick in 0..10_000 {
+            player.record_position(tick);
+        }
+
+        assert_eq!(player.prev_pos.len() as u64, MAX_INPUT_HISTORY);
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    collections::HashMap,
+    env,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use log::warn;
+use serde::Serialize;
+use tokio::time::Instant;
+use warp::http::StatusCode;
+
+use crate::Server;
+
+fn from_env<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+#[derive(Debug, Clone)]
+pub struct LimitsConfig {
+    // every open socket on this process
+    pub max_connections: usize,
+    // players in the room, including the ones holding a slot while they can still resume
+    pub max_room_connections: usize,
+    pub max_per_ip: usize,
+    // X-Forwarded-For is only believed when the request comes from one of these, None trusts
+    // loopback and private addresses, where a reverse proxy usually sits
+    pub trusted_proxies: Option<Vec<IpAddr>>,
+    // token bucket for incoming messages, refilled at `message_rate` per second
+    pub message_rate: f64,
+    pub message_burst: f64,
+    pub max_frame_bytes: usize,
+}
+
+impl LimitsConfig {
+    pub fn from_env() -> Self {
+        // an empty TRUSTED_PROXIES trusts no proxy at all
+        let trusted_proxies = env::var("TRUSTED_PROXIES").ok().map(|proxies| {
+            proxies
+                .split(',')
+                .filter_map(|proxy| proxy.trim().parse().ok())
+                .collect()
+        });
+
+        Self {
+            max_connections: from_env("MAX_CONNECTIONS", 1000),
+            max_room_connections: from_env("MAX_ROOM_CONNECTIONS", 200),
+            max_per_ip: from_env("MAX_CONNECTIONS_PER_IP", 8),
+            trusted_proxies,
+            message_rate: from_env("MESSAGE_RATE", 30.0),
+            message_burst: from_env("MESSAGE_BURST", 60.0),
+            max_frame_bytes: from_env("MAX_FRAME_BYTES", 16 * 1024),
+        }
+    }
+
+    fn trusts(&self, ip: &IpAddr) -> bool {
+        match &self.trusted_proxies {
+            Some(proxies) => proxies.contains(ip),
+            None => is_private(ip),
+        }
+    }
+}
+
+fn is_private(ip: &IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        // fc00::/7 unique local and fe80::/10 link local
+        IpAddr::V6(ip) => {
+            ip.is_loopback()
+                || (ip.segments()[0] & 0xfe00) == 0xfc00
+                || (ip.segments()[0] & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    pub open: AtomicUsize,
+    pub per_ip: std::sync::Mutex<HashMap<IpAddr, usize>>,
+    pub dropped_messages: AtomicU64,
+}
+
+impl ConnectionStats {
+    // nothing panics while holding the lock, a poisoned map is still accurate
+    fn per_ip(&self) -> std::sync::MutexGuard<'_, HashMap<IpAddr, usize>> {
+        self.per_ip
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsSnapshot {
+    pub open: usize,
+    pub room: usize,
+    pub dropped_messages: u64,
+    pub per_ip: HashMap<IpAddr, usize>,
+}
+
+pub async fn snapshot(server: &Server) -> StatsSnapshot {
+    let stats = &server.connection_stats;
+
+    StatsSnapshot {
+        open: stats.open.load(Ordering::Relaxed),
+        room: room_connections(server).await,
+        dropped_messages: stats.dropped_messages.load(Ordering::Relaxed),
+        per_ip: stats.per_ip().clone(),
+    }
+}
+
+// walks X-Forwarded-For from the right, the first address that isn't one of our proxies is
+// the client
+pub fn client_ip(
+    config: &LimitsConfig,
+    remote: Option<SocketAddr>,
+    forwarded_for: Option<String>,
+) -> Option<IpAddr> {
+    let remote = remote?.ip();
+
+    if !config.trusts(&remote) {
+        return Some(remote);
+    }
+
+    let forwarded: Vec<IpAddr> = forwarded_for
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect();
+
+    forwarded
+        .iter()
+        .rev()
+        .find(|ip| !config.trusts(ip))
+        .or(forwarded.first())
+        .copied()
+        .or(Some(remote))
+}
+
+async fn room_connections(server: &Server) -> usize {
+    let connected = server.connections.read().await.len();
+    let resumable = server.sessions.lock().await.disconnected.len();
+
+    connected + resumable
+}
+
+// held for as long as the socket is open, dropping it frees the slot
+pub struct ConnectionPermit {
+    server: Arc<Server>,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let stats = &self.server.connection_stats;
+        stats.open.fetch_sub(1, Ordering::Relaxed);
+
+        if let Some(ip) = self.ip {
+            let mut per_ip = stats.per_ip();
+            if let Some(count) = per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+pub async fn acquire(
+    server: Arc<Server>,
+    ip: Option<IpAddr>,
+) -> Result<ConnectionPermit, (StatusCode, &'static str)> {
+    let limits = &server.limits;
+    let stats = &server.connection_stats;
+
+    // the slot is taken before either limit is checked, so connections arriving together can't
+    // all see the same last free slot. Every socket here is in the room, alongside the players
+    // still holding a slot while they can resume
+    let open = stats.open.fetch_add(1, Ordering::Relaxed);
+    let resumable = server.sessions.lock().await.disconnected.len();
+
+    if open + resumable >= limits.max_room_connections {
+        stats.open.fetch_sub(1, Ordering::Relaxed);
+        warn!("Room {} is full, turning away {:?}", server.room, ip);
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "room is full"));
+    }
+
+    if open >= limits.max_connections {
+        stats.open.fetch_sub(1, Ordering::Relaxed);
+        warn!("Server is full, turning away {:?}", ip);
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "server is full"));
+    }
+
+    if let Some(ip) = ip {
+        let mut per_ip = stats.per_ip();
+        let count = per_ip.entry(ip).or_insert(0);
+
+        if *count >= limits.max_per_ip {
+            drop(per_ip);
+            stats.open.fetch_sub(1, Ordering::Relaxed);
+            warn!("Too many connections from {}", ip);
+            return Err((StatusCode::TOO_MANY_REQUESTS, "too many connections"));
+        }
+
+        *count += 1;
+    }
+
+    Ok(ConnectionPermit { server, ip })
+}
+
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(config: &LimitsConfig) -> Self {
+        Self {
+            rate: config.message_rate,
+            burst: config.message_burst,
+            tokens: config.message_burst,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.burst);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn a_burst_never_overfills_the_room() {
+        let server = Arc::new(Server {
+            limits: LimitsConfig {
+                max_room_connections: 5,
+                max_per_ip: 100,
+                ..LimitsConfig::from_env()
+            },
+            ..Server::default()
+        });
+
+        let attempts = (0..50).map(|_| tokio::spawn(acquire(server.clone(), None)));
+
+        let mut permits = Vec::new();
+        for attempt in attempts {
+            if let Ok(permit) = attempt.await.unwrap() {
+                permits.push(permit);
+            }
+        }
+
+        assert_eq!(permits.len(), 5);
+
+        drop(permits);
+        assert_eq!(server.connection_stats.open.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,307 @@
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+
+use bech32::{ToBase32, Variant};
+use log::{error, info};
+use rand::Rng;
+use serde_json::json;
+use tokio::time::Instant;
+use warp::{Filter, Rejection, Reply};
+
+use crate::{messages::WithdrawClaim, Server};
+
+#[derive(Debug, Clone)]
+pub struct LnurlConfig {
+    pub withdraw_enabled: bool,
+    pub public_url: String,
+    pub claim_ttl: Duration,
+}
+
+impl LnurlConfig {
+    pub fn from_env() -> Self {
+        let withdraw_enabled = env::var("LNURL_WITHDRAW").as_deref() == Ok("true");
+
+        let public_url = env::var("PUBLIC_URL")
+            .unwrap_or_else(|_| "http://localhost:3030".to_string())
+            .trim_end_matches('/')
+            .to_string();
+
+        let claim_ttl = env::var("LNURL_CLAIM_TTL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(24 * 60 * 60));
+
+        Self {
+            withdraw_enabled,
+            public_url,
+            claim_ttl,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingWithdraw {
+    pub amount: u64,
+    pub created: Instant,
+}
+
+pub fn encode_lnurl(url: &str) -> Option<String> {
+    match bech32::encode("lnurl", url.as_bytes().to_base32(), Variant::Bech32) {
+        Ok(lnurl) => Some(lnurl.to_uppercase()),
+        Err(e) => {
+            error!("Failed to encode lnurl {}: {}", url, e);
+            None
+        }
+    }
+}
+
+pub fn random_k1() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    hex::encode(bytes)
+}
+
+// one-time claim for a player without a lightning address, redeemed with any LNURL wallet
+pub async fn create_claim(server: &Server, amount: u64) -> Option<WithdrawClaim> {
+    let k1 = random_k1();
+    let lnurl = encode_lnurl(&format!(
+        "{}/lnurl/withdraw/{}",
+        server.lnurl.public_url, k1
+    ))?;
+
+    {
+        let mut claims = server.withdraw_claims.lock().await;
+        claims.retain(|_, claim| claim.created.elapsed() < server.lnurl.claim_ttl);
+        claims.insert(
+            k1,
+            PendingWithdraw {
+                amount,
+                created: Instant::now(),
+            },
+        );
+    }
+
+    let qr = format!("lightning:{}", lnurl);
+
+    Some(WithdrawClaim::new(amount, lnurl, qr))
+}
+
+pub fn routes(
+    server: Arc<Server>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let server = warp::any().map(move || server.clone());
+
+    let callback = warp::path!("lnurl" / "withdraw" / "callback")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(server.clone())
+        .and_then(withdraw_callback);
+
+    let request = warp::path!("lnurl" / "withdraw" / String)
+        .and(warp::get())
+        .and(server)
+        .and_then(withdraw_request);
+
+    callback.or(request)
+}
+
+fn lnurl_error(reason: &str) -> warp::reply::Json {
+    warp::reply::json(&json!({ "status": "ERROR", "reason": reason }))
+}
+
+async fn withdraw_request(k1: String, server: Arc<Server>) -> Result<impl Reply, Rejection> {
+    let claims = server.withdraw_claims.lock().await;
+
+    let claim = match claims.get(&k1) {
+        Some(claim) if claim.created.elapsed() < server.lnurl.claim_ttl => claim,
+        _ => return Ok(lnurl_error("Unknown or expired claim")),
+    };
+
+    Ok(warp::reply::json(&json!({
+        "tag": "withdrawRequest",
+        "callback": format!("{}/lnurl/withdraw/callback", server.lnurl.public_url),
+        "k1": k1,
+        "defaultDescription": server.rewards.comment,
+        "minWithdrawable": claim.amount,
+        "maxWithdrawable": claim.amount,
+    })))
+}
+
+async fn withdraw_callback(
+    query: HashMap<String, String>,
+    server: Arc<Server>,
+) -> Result<impl Reply, Rejection> {
+    let (k1, invoice) = match (query.get("k1"), query.get("pr")) {
+        (Some(k1), Some(invoice)) => (k1.clone(), invoice.clone()),
+        _ => return Ok(lnurl_error("Missing k1 or pr")),
+    };
+
+    let claim = {
+        let mut claims = server.withdraw_claims.lock().await;
+
+        let claim = match claims.remove(&k1) {
+            Some(claim) if claim.created.elapsed() < server.lnurl.claim_ttl => claim,
+            _ => return Ok(lnurl_error("Unknown or expired claim")),
+        };
+
+        match invoice_amount_msats(&invoice) {
+            Some(amount) if amount > 0 && amount <= claim.amount => claim,
+            _ => {
+                claims.insert(k1, claim);
+                return Ok(lnurl_error("Invoice amount does not match claim"));
+            }
+        }
+    };
+
+    tokio::spawn(async move {
+        let payment_response = server
+            .payment_provider
+            .pay_invoice(&invoice, &server.rewards.comment)
+            .await;
+
+        match payment_response {
+            Ok(receipt) => info!("Withdraw {} paid: {:?}", k1, receipt.reference),
+            Err(e) => {
+                error!("Withdraw {} failed: {}", k1, e);
+                server.withdraw_claims.lock().await.insert(k1, claim);
+            }
+        }
+    });
+
+    Ok(warp::reply::json(&json!({ "status": "OK" })))
+}
+
+// the amount lives in the bolt11 human readable part, e.g. lnbc2500u1... is 2500 micro btc
+pub fn invoice_amount_msats(invoice: &str) -> Option<u64> {
+    let invoice = invoice.to_lowercase();
+    let invoice = invoice.strip_prefix("lightning:").unwrap_or(&invoice);
+    let hrp = &invoice[..invoice.rfind('1')?];
+    let amount = hrp
+        .strip_prefix("ln")?
+        .trim_start_matches(|c: char| c.is_ascii_alphabetic());
+
+    let (value, multiplier) = match amount.chars().last()? {
+        'm' | 'u' | 'n' | 'p' => amount.split_at(amount.len() - 1),
+        _ => (amount, ""),
+    };
+
+    let value: u64 = value.parse().ok()?;
+
+    match multiplier {
+        "m" => value.checked_mul(100_000_000),
+        "u" => value.checked_mul(100_000),
+        "n" => value.checked_mul(100),
+        "p" if value % 10 == 0 => Some(value / 10),
+        "" => value.checked_mul(100_000_000_000),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::provider::MockProvider;
+
+    fn server() -> Arc<Server> {
+        Arc::new(Server {
+            payment_provider: Box::new(MockProvider::default()),
+            ..Server::default()
+        })
+    }
+
+    async fn claim(server: &Server, amount: u64) -> String {
+        create_claim(server, amount).await.unwrap();
+        server
+            .withdraw_claims
+            .lock()
+            .await
+            .keys()
+            .next()
+            .unwrap()
+            .clone()
+    }
+
+    async fn callback(server: &Arc<Server>, k1: &str, invoice: &str) -> Value {
+        let query = HashMap::from([
+            ("k1".to_string(), k1.to_string()),
+            ("pr".to_string(), invoice.to_string()),
+        ]);
+
+        let reply = withdraw_callback(query, server.clone())
+            .await
+            .unwrap()
+            .into_response();
+        let body = warp::hyper::body::to_bytes(reply.into_body())
+            .await
+            .unwrap();
+
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn invoice_amounts_come_from_the_prefix() {
+        assert_eq!(invoice_amount_msats("lnbc2500u1pvjluez"), Some(250_000_000));
+        assert_eq!(invoice_amount_msats("lnbc20m1pvjluez"), Some(2_000_000_000));
+        assert_eq!(invoice_amount_msats("lnbc2500n1pvjluez"), Some(250_000));
+        assert_eq!(invoice_amount_msats("lntb10p1pvjluez"), Some(1));
+        assert_eq!(
+            invoice_amount_msats("LIGHTNING:LNBC2500N1PVJLUEZ"),
+            Some(250_000)
+        );
+
+        // no amount, sub-msat amounts and unknown multipliers
+        assert_eq!(invoice_amount_msats("lnbc1pvjluez"), None);
+        assert_eq!(invoice_amount_msats("lnbc15p1pvjluez"), None);
+        assert_eq!(invoice_amount_msats("lnbc2500x1pvjluez"), None);
+    }
+
+    #[tokio::test]
+    async fn claims_are_single_use() {
+        let server = server();
+        let k1 = claim(&server, 250_000).await;
+
+        let paid = callback(&server, &k1, "lnbc2500n1pvjluez").await;
+        assert_eq!(paid["status"], "OK");
+
+        let again = callback(&server, &k1, "lnbc2500n1pvjluez").await;
+        assert_eq!(again["status"], "ERROR");
+    }
+
+    #[tokio::test]
+    async fn mismatched_invoices_keep_the_claim() {
+        let server = server();
+        let k1 = claim(&server, 250_000).await;
+
+        for invoice in ["lnbc5000n1pvjluez", "lnbc1pvjluez"] {
+            let refused = callback(&server, &k1, invoice).await;
+            assert_eq!(refused["status"], "ERROR");
+            assert!(server.withdraw_claims.lock().await.contains_key(&k1));
+        }
+
+        // less than the claim is fine, the rest isn't owed twice
+        let paid = callback(&server, &k1, "lnbc1000n1pvjluez").await;
+        assert_eq!(paid["status"], "OK");
+        assert!(!server.withdraw_claims.lock().await.contains_key(&k1));
+    }
+
+    #[tokio::test]
+    async fn failed_withdraws_restore_the_claim() {
+        let server = server();
+        let k1 = claim(&server, 250_000).await;
+
+        let accepted = callback(&server, &k1, "lnbc2500n1fail").await;
+        assert_eq!(accepted["status"], "OK");
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !server.withdraw_claims.lock().await.contains_key(&k1) {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("the claim was not restored");
+
+        let retried = callback(&server, &k1, "lnbc2500n1pvjluez").await;
+        assert_eq!(retried["status"], "OK");
+    }
+}
@@ -0,0 +1,404 @@
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+
+use log::{error, info, warn};
+use redis::Commands;
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+use serde_json::json;
+use tokio::time::Instant;
+use uuid::Uuid;
+use warp::{Filter, Rejection, Reply};
+
+use crate::{
+    auth::{now_secs, SessionClaims},
+    lnurl::{encode_lnurl, random_k1},
+    messages::{LoggedIn, LoginChallenge, NetworkMessage},
+    Server,
+};
+
+const ACCOUNTS_KEY: &str = "lnurl_auth_accounts";
+const NAME_OWNERS_KEY: &str = "name_owners";
+const ADDRESS_OWNERS_KEY: &str = "payout_address_owners";
+
+#[derive(Debug, Clone)]
+pub struct PendingLogin {
+    pub client_id: Uuid,
+    pub created: Instant,
+}
+
+// LNURL-auth linking keys map to a persistent account id, and names claimed by an account
+// can't be used by anyone else. Payout addresses belong to the account of the last session
+// token that verified the address
+#[derive(Debug)]
+pub struct Accounts {
+    pub challenge_ttl: Duration,
+    pub session_ttl: Duration,
+    pub pending: HashMap<String, PendingLogin>,
+    pub linked_keys: HashMap<String, String>,
+    pub name_owners: HashMap<String, String>,
+    pub address_owners: HashMap<String, String>,
+}
+
+impl Accounts {
+    pub fn from_env() -> Self {
+        let challenge_ttl = env::var("LOGIN_CHALLENGE_TTL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(5 * 60));
+
+        let session_ttl = env::var("LOGIN_SESSION_TTL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30 * 24 * 60 * 60));
+
+        Self {
+            challenge_ttl,
+            session_ttl,
+            pending: HashMap::new(),
+            linked_keys: HashMap::new(),
+            name_owners: HashMap::new(),
+            address_owners: HashMap::new(),
+        }
+    }
+
+    fn name_key(name: &str) -> String {
+        name.trim().to_lowercase()
+    }
+
+    pub fn name_owner(&self, name: &str) -> Option<&String> {
+        self.name_owners.get(&Self::name_key(name))
+    }
+}
+
+pub async fn load_accounts(server: &Server) {
+    let (linked_keys, name_owners, address_owners) = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => {
+            let linked_keys: HashMap<String, String> =
+                redis_client.hgetall(ACCOUNTS_KEY).unwrap_or_default();
+            let mut name_owners: HashMap<String, String> =
+                redis_client.hgetall(NAME_OWNERS_KEY).unwrap_or_default();
+
+            let address_owners: HashMap<String, String> =
+                redis_client.hgetall(ADDRESS_OWNERS_KEY).unwrap_or_default();
+
+            // payout addresses used to be claimed here by whoever played with them first,
+            // display names can't contain an @
+            let addresses: Vec<String> = name_owners
+                .keys()
+                .filter(|name| name.contains('@'))
+                .cloned()
+                .collect();
+
+            if !addresses.is_empty() {
+                info!("Releasing {} claimed payout addresses", addresses.len());
+                name_owners.retain(|name, _| !name.contains('@'));
+                let _: () = redis_client
+                    .hdel(NAME_OWNERS_KEY, &addresses)
+                    .unwrap_or_else(|e| error!("Failed to release payout addresses: {}", e));
+            }
+
+            (linked_keys, name_owners, address_owners)
+        }
+        None => return,
+    };
+
+    info!(
+        "Loaded {} accounts, {} claimed names and {} verified payout addresses",
+        linked_keys.len(),
+        name_owners.len(),
+        address_owners.len()
+    );
+
+    let mut accounts = server.accounts.lock().await;
+    accounts.linked_keys = linked_keys;
+    accounts.name_owners = name_owners;
+    accounts.address_owners = address_owners;
+}
+
+async fn persist(server: &Server, key: &str, field: &str, value: &str) {
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        let _: () = redis_client
+            .hset(key, field, value)
+            .unwrap_or_else(|e| error!("Failed to persist {} {}: {}", key, field, e));
+    }
+}
+
+pub async fn login_challenge(server: &Server, client_id: Uuid) -> Option<LoginChallenge> {
+    let k1 = random_k1();
+    let lnurl = encode_lnurl(&format!(
+        "{}/lnurl/auth?tag=login&k1={}&action=login",
+        server.lnurl.public_url, k1
+    ))?;
+
+    {
+        let mut accounts = server.accounts.lock().await;
+        let ttl = accounts.challenge_ttl;
+        accounts
+            .pending
+            .retain(|_, pending| pending.created.elapsed() < ttl);
+        accounts.pending.insert(
+            k1,
+            PendingLogin {
+                client_id,
+                created: Instant::now(),
+            },
+        );
+    }
+
+    let qr = format!("lightning:{}", lnurl);
+
+    Some(LoginChallenge::new(lnurl, qr))
+}
+
+// the wallet signs k1 with its linking key, a DER signature over the raw 32 bytes
+pub fn verify_signature(k1: &str, sig: &str, key: &str) -> Result<(), String> {
+    let k1 = hex::decode(k1).map_err(|e| format!("invalid k1: {}", e))?;
+    let message = Message::from_digest_slice(&k1).map_err(|e| format!("invalid k1: {}", e))?;
+
+    let sig = hex::decode(sig).map_err(|e| format!("invalid sig: {}", e))?;
+    let mut signature = Signature::from_der(&sig).map_err(|e| format!("invalid sig: {}", e))?;
+    signature.normalize_s();
+
+    let key = hex::decode(key).map_err(|e| format!("invalid key: {}", e))?;
+    let key = PublicKey::from_slice(&key).map_err(|e| format!("invalid key: {}", e))?;
+
+    Secp256k1::verification_only()
+        .verify_ecdsa(&message, &signature, &key)
+        .map_err(|_| "signature does not match".to_string())
+}
+
+// first login with a linking key creates the account, later ones find it again
+async fn account_for_key(server: &Server, key: &str) -> String {
+    let (account, created) = {
+        let mut accounts = server.accounts.lock().await;
+        match accounts.linked_keys.get(key) {
+            Some(account) => (account.clone(), false),
+            None => {
+                let account = Uuid::new_v4().to_string();
+                accounts
+                    .linked_keys
+                    .insert(key.to_string(), account.clone());
+                (account, true)
+            }
+        }
+    };
+
+    if created {
+        info!("New account {} for linking key {}", account, key);
+        persist(server, ACCOUNTS_KEY, key, &account).await;
+    }
+
+    account
+}
+
+// display names belong to the first account that plays with them, checked and claimed under
+// one lock so two logins can't both take the same name
+pub async fn check_name(server: &Server, name: &str, account: Option<&str>) -> Result<(), String> {
+    let (name_key, account) = {
+        let mut accounts = server.accounts.lock().await;
+
+        match (accounts.name_owner(name), account) {
+            (Some(owner), _) if Some(owner.as_str()) != account => {
+                return Err(format!("{} belongs to another player", name))
+            }
+            (Some(_), _) | (None, None) => return Ok(()),
+            (None, Some(account)) => {
+                let name_key = Accounts::name_key(name);
+                accounts
+                    .name_owners
+                    .insert(name_key.clone(), account.to_string());
+                (name_key, account)
+            }
+        }
+    };
+
+    persist(server, NAME_OWNERS_KEY, &name_key, account).await;
+    info!("{} claimed {}", account, name);
+    Ok(())
+}
+
+// logging in proves nothing about who controls an address, a session token that verified the
+// address does. That binds the address to the token's account, after which only that account
+// is paid to it
+pub async fn check_payout_address(
+    server: &Server,
+    ln_address: &str,
+    claims: Option<&SessionClaims>,
+    account: Option<&str>,
+) -> Result<(), String> {
+    let address_key = ln_address.trim().to_lowercase();
+
+    let verified = claims.filter(|claims| {
+        claims
+            .ln_address
+            .as_deref()
+            .is_some_and(|verified| verified.trim().eq_ignore_ascii_case(&address_key))
+    });
+
+    let Some(claims) = verified else {
+        let accounts = server.accounts.lock().await;
+        return match accounts.address_owners.get(&address_key) {
+            Some(owner) if Some(owner.as_str()) != account => {
+                Err("LN address was verified by another player".to_string())
+            }
+            _ => Ok(()),
+        };
+    };
+
+    let rebound = server
+        .accounts
+        .lock()
+        .await
+        .address_owners
+        .insert(address_key.clone(), claims.subject.clone())
+        .as_ref()
+        != Some(&claims.subject);
+
+    if rebound {
+        persist(server, ADDRESS_OWNERS_KEY, &address_key, &claims.subject).await;
+        info!("{} verified a payout address", claims.subject);
+    }
+
+    Ok(())
+}
+
+pub fn routes(
+    server: Arc<Server>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("lnurl" / "auth")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::any().map(move || server.clone()))
+        .and_then(auth_callback)
+}
+
+fn lnurl_error(reason: &str) -> warp::reply::Json {
+    warp::reply::json(&json!({ "status": "ERROR", "reason": reason }))
+}
+
+async fn auth_callback(
+    query: HashMap<String, String>,
+    server: Arc<Server>,
+) -> Result<impl Reply, Rejection> {
+    let (k1, sig, key) = match (query.get("k1"), query.get("sig"), query.get("key")) {
+        (Some(k1), Some(sig), Some(key)) => (k1, sig, key.to_lowercase()),
+        _ => return Ok(lnurl_error("Missing k1, sig or key")),
+    };
+
+    let pending = {
+        let mut accounts = server.accounts.lock().await;
+        match accounts.pending.get(k1) {
+            Some(pending) if pending.created.elapsed() < accounts.challenge_ttl => {}
+            _ => return Ok(lnurl_error("Unknown or expired k1")),
+        }
+
+        if let Err(reason) = verify_signature(k1, sig, &key) {
+            warn!("LNURL-auth failed for {}: {}", key, reason);
+            return Ok(lnurl_error("Invalid signature"));
+        }
+
+        match accounts.pending.remove(k1) {
+            Some(pending) => pending,
+            None => return Ok(lnurl_error("Unknown or expired k1")),
+        }
+    };
+
+    let account = account_for_key(&server, &key).await;
+    let session_ttl = server.accounts.lock().await.session_ttl;
+
+    let claims = SessionClaims {
+        subject: account.clone(),
+        ln_address: None,
+        expires: now_secs().saturating_add(session_ttl.as_secs()),
+    };
+
+    let token = match server.auth.issue(&claims) {
+        Some(token) => token,
+        None => return Ok(lnurl_error("Failed to issue session")),
+    };
+
+    info!("{} logged in as {}", pending.client_id, account);
+
+    if let Some(connection) = server.connections.read().await.get(&pending.client_id) {
+        let logged_in = NetworkMessage::LoggedIn(LoggedIn::new(account, token));
+        if let Err(e) = connection.send(logged_in) {
+            error!("Failed to send message over WebSocket: {}", e);
+        }
+    }
+
+    Ok(warp::reply::json(&json!({ "status": "OK" })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(subject: &str, ln_address: Option<&str>) -> SessionClaims {
+        SessionClaims {
+            subject: subject.to_string(),
+            ln_address: ln_address.map(str::to_string),
+            expires: u64::MAX,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_name_goes_to_one_account() {
+        let server = Arc::new(Server::default());
+
+        let logins = (0..10).map(|i| {
+            let server = server.clone();
+            tokio::spawn(async move {
+                check_name(&server, "Rain Runner", Some(&format!("account-{}", i))).await
+            })
+        });
+
+        let mut claimed = 0;
+        for login in logins {
+            if login.await.unwrap().is_ok() {
+                claimed += 1;
+            }
+        }
+
+        assert_eq!(claimed, 1);
+    }
+
+    #[tokio::test]
+    async fn verified_addresses_only_pay_their_owner() {
+        let server = Server::default();
+        let address = "runner@example.com";
+
+        // anyone can use an address nobody has verified yet
+        assert!(check_payout_address(&server, address, None, Some("other"))
+            .await
+            .is_ok());
+
+        let owner = claims("owner", Some("Runner@Example.com"));
+        assert!(
+            check_payout_address(&server, address, Some(&owner), Some("owner"))
+                .await
+                .is_ok()
+        );
+
+        assert!(check_payout_address(&server, address, None, Some("owner"))
+            .await
+            .is_ok());
+        assert!(check_payout_address(&server, address, None, Some("other"))
+            .await
+            .is_err());
+        assert!(check_payout_address(&server, address, None, None)
+            .await
+            .is_err());
+
+        // proving control again from another account moves the address to it
+        let other = claims("other", Some(address));
+        assert!(
+            check_payout_address(&server, address, Some(&other), Some("other"))
+                .await
+                .is_ok()
+        );
+        assert!(check_payout_address(&server, address, None, Some("owner"))
+            .await
+            .is_err());
+    }
+}
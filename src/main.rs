@@ -1,65 +1,179 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     env,
-    sync::{atomic::AtomicU64, Arc},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize},
+        Arc,
+    },
 };
 
 use game_loop::PlayerEntity;
-use messages::{ObjectMsg, PlayerInput};
-use rand::Rng;
+use messages::{HighScore, ObjectMsg, PaymentUpdate, PlayerInput, Season};
 
-use serde_json::Value;
-use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio::sync::{broadcast, mpsc, Mutex, RwLock};
 
 use uuid::Uuid;
 use warp::Filter;
-use ws::new_websocket;
-use zebedee_rust::ZebedeeClient;
 
+use crate::anticheat::AnticheatConfig;
+use crate::auth::AuthConfig;
+use crate::bans::Ban;
+use crate::challenge::{ChallengeConfig, PayoutVolume};
+use crate::clock::Clock;
+use crate::entry::{EntryConfig, RoundFinish};
+use crate::events::{Event, EventConfig};
+use crate::fair::FairState;
 use crate::game_loop::game_loop;
+use crate::idle::IdleConfig;
+use crate::limits::{ConnectionStats, LimitsConfig};
+use crate::lnurl::{LnurlConfig, PendingWithdraw};
+use crate::login::Accounts;
 use crate::messages::NetworkMessage;
-
+use crate::names::NameRules;
+use crate::payments::{PayoutConfig, ReviewItem};
+use crate::provider::PaymentProvider;
+use crate::results::ResultSigner;
+use crate::rewards::RewardSchedule;
+use crate::seasons::SeasonConfig;
+use crate::session::Sessions;
+use crate::spawns::SpawnConfig;
+
+mod admin;
+mod anticheat;
+mod auth;
+mod bans;
+mod challenge;
+mod clock;
+mod entry;
+mod events;
+mod fair;
 mod game_loop;
+mod idle;
+mod leaderboard;
+mod lifecycle;
+mod limits;
+mod lnurl;
+mod login;
 mod messages;
+mod names;
+mod payments;
+mod provider;
+mod results;
+mod rewards;
+mod seasons;
+mod session;
+mod spawns;
 mod ws;
 
 pub struct Server {
     pub seed: AtomicU64,
     pub tick: AtomicU64,
-    pub high_scores: RwLock<Vec<(String, u64)>>,
+    pub high_scores: RwLock<Vec<HighScore>>,
     pub connections: RwLock<HashMap<Uuid, mpsc::UnboundedSender<NetworkMessage>>>,
     pub player_inputs: Mutex<HashMap<Uuid, Vec<PlayerInput>>>,
     pub player_names: Mutex<HashMap<Uuid, PlayerEntity>>,
     pub redis: Mutex<Option<redis::Connection>>,
-    pub zebedee_client: Mutex<ZebedeeClient>,
+    pub payment_provider: Box<dyn PaymentProvider>,
     pub objects: Mutex<Option<ObjectMsg>>,
+    pub room: String,
+    pub rewards: RewardSchedule,
+    pub payouts: PayoutConfig,
+    pub carry_over: Mutex<HashMap<String, u64>>,
+    pub run_payments: Mutex<HashMap<Uuid, Vec<PaymentUpdate>>>,
+    // payments of ended runs still waiting on the provider, by payment id
+    pub unresolved_payments: Mutex<HashMap<Uuid, PaymentUpdate>>,
+    pub lnurl: LnurlConfig,
+    pub withdraw_claims: Mutex<HashMap<String, PendingWithdraw>>,
+    pub entry: EntryConfig,
+    pub prize_pool: AtomicU64,
+    pub round_finishers: Mutex<Vec<RoundFinish>>,
+    // clients waiting on their entry invoice
+    pub pending_entries: Mutex<HashSet<Uuid>>,
+    pub challenge: ChallengeConfig,
+    pub payout_volume: Mutex<HashMap<String, PayoutVolume>>,
+    pub anticheat: AnticheatConfig,
+    pub review_queue: Mutex<HashMap<Uuid, ReviewItem>>,
+    pub admin_tokens: HashMap<String, String>,
+    pub fair: Mutex<FairState>,
+    pub spawns: SpawnConfig,
+    pub upcoming_spawns: Mutex<Option<ObjectMsg>>,
+    pub result_signer: ResultSigner,
+    pub sessions: Mutex<Sessions>,
+    pub idle: IdleConfig,
+    pub limits: LimitsConfig,
+    pub connection_stats: ConnectionStats,
+    pub auth: AuthConfig,
+    pub accounts: Mutex<Accounts>,
+    pub names: NameRules,
+    pub display_names: Mutex<HashMap<String, Uuid>>,
+    pub bans: Mutex<HashMap<String, Ban>>,
+    pub frozen_names: Mutex<HashSet<String>>,
+    pub clock: Box<dyn Clock>,
+    pub seasons: SeasonConfig,
+    pub season: RwLock<Season>,
+    pub events: broadcast::Sender<Event>,
+    pub event_config: EventConfig,
+    pub event_subscribers: AtomicUsize,
 }
 
 impl Default for Server {
     fn default() -> Self {
-        #[cfg(not(debug_assertions))]
-        let zebedee_client = {
-            let api_key_json: String = env::var("ZBD_API_KEY").unwrap();
-            let value: Value = serde_json::from_str(&api_key_json).unwrap();
-
-            let api_key = value["ZBD_API_KEY"].as_str().unwrap().to_string();
-
-            Mutex::new(ZebedeeClient::new().apikey(api_key).build())
-        };
-
-        #[cfg(debug_assertions)]
-        let zebedee_client = Mutex::new(ZebedeeClient::new().apikey("test".to_string()).build());
+        let room = env::var("ROOM").unwrap_or_else(|_| "default".to_string());
+        let rewards = RewardSchedule::from_env(&room);
+        let fair = FairState::new(&room);
 
         Self {
-            seed: rand::thread_rng().gen::<u64>().into(),
+            seed: fair.current.sim_seed.into(),
             tick: AtomicU64::new(0),
             high_scores: RwLock::new(Vec::new()),
             connections: RwLock::new(HashMap::new()),
             player_inputs: Mutex::new(HashMap::new()),
             player_names: Mutex::new(HashMap::new()),
             redis: Mutex::new(None),
-            zebedee_client,
+            payment_provider: provider::from_env(),
             objects: Mutex::new(None),
+            room,
+            rewards,
+            payouts: PayoutConfig::from_env(),
+            carry_over: Mutex::new(HashMap::new()),
+            run_payments: Mutex::new(HashMap::new()),
+            unresolved_payments: Mutex::new(HashMap::new()),
+            lnurl: LnurlConfig::from_env(),
+            withdraw_claims: Mutex::new(HashMap::new()),
+            entry: EntryConfig::from_env(),
+            prize_pool: AtomicU64::new(0),
+            round_finishers: Mutex::new(Vec::new()),
+            pending_entries: Mutex::new(HashSet::new()),
+            challenge: ChallengeConfig::from_env(),
+            payout_volume: Mutex::new(HashMap::new()),
+            anticheat: AnticheatConfig::from_env(),
+            review_queue: Mutex::new(HashMap::new()),
+            admin_tokens: admin::admin_tokens(),
+            fair: Mutex::new(fair),
+            spawns: SpawnConfig::from_env(),
+            upcoming_spawns: Mutex::new(None),
+            result_signer: ResultSigner::from_env(),
+            sessions: Mutex::new(Sessions::from_env()),
+            idle: IdleConfig::from_env(),
+            limits: LimitsConfig::from_env(),
+            connection_stats: ConnectionStats::default(),
+            auth: AuthConfig::from_env(),
+            accounts: Mutex::new(Accounts::from_env()),
+            names: NameRules::from_env(),
+            display_names: Mutex::new(HashMap::new()),
+            bans: Mutex::new(HashMap::new()),
+            frozen_names: Mutex::new(HashSet::new()),
+            clock: clock::from_env(),
+            seasons: SeasonConfig::from_env(),
+            // replaced by the stored season on startup
+            season: RwLock::new(Season {
+                id: 1,
+                start: 0,
+                end: None,
+            }),
+            events: events::channel(),
+            event_config: EventConfig::from_env(),
+            event_subscribers: AtomicUsize::new(0),
         }
     }
 }
@@ -68,6 +182,21 @@ impl Default for Server {
 async fn main() {
     pretty_env_logger::init_timed();
 
+    // satrunner_server verify <server_seed> <entropy> <commitment>
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("verify") {
+        match (args.get(2), args.get(3), args.get(4)) {
+            (Some(server_seed), Some(entropy), Some(commitment)) => {
+                match fair::verify(server_seed, entropy, commitment) {
+                    Ok(sim_seed) => println!("valid, simulation seed {}", sim_seed),
+                    Err(reason) => println!("invalid: {}", reason),
+                }
+            }
+            _ => println!("usage: verify <server_seed> <entropy> <commitment>"),
+        }
+        return;
+    }
+
     let server = Arc::new(Server::default());
     let server_clone = server.clone();
 
@@ -75,15 +204,34 @@ async fn main() {
         game_loop(server_clone).await;
     });
 
-    let server = warp::any().map(move || server.clone());
+    let lnurl_routes = lnurl::routes(server.clone());
+    let admin_routes = admin::routes(server.clone());
+    let fair_routes = fair::routes(server.clone());
+    let results_routes = results::routes(server.clone());
+    let auth_routes = auth::routes(server.clone());
+    let login_routes = login::routes(server.clone());
+    let ban_routes = bans::routes(server.clone());
+    let leaderboard_routes = leaderboard::routes(server.clone());
+    let season_routes = seasons::routes(server.clone());
+    let event_routes = events::routes(server.clone());
+    let ws_routes = ws::routes(server);
 
     let health_check = warp::path("health")
         .and(warp::get())
         .map(|| warp::reply::with_status("OK", warp::http::StatusCode::OK));
 
-    let routes = health_check.or(warp::path("run").and(warp::ws()).and(server).map(
-        |ws: warp::ws::Ws, server| ws.on_upgrade(move |socket| new_websocket(socket, server)),
-    ));
+    let routes = health_check
+        .or(lnurl_routes)
+        .or(admin_routes)
+        .or(fair_routes)
+        .or(results_routes)
+        .or(auth_routes)
+        .or(login_routes)
+        .or(ban_routes)
+        .or(leaderboard_routes)
+        .or(season_routes)
+        .or(event_routes)
+        .or(ws_routes);
 
     warp::serve(routes).run(([0, 0, 0, 0], 3030)).await;
 }
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use speedy::{Readable, Writable};
 use uuid::Uuid;
 
@@ -11,12 +12,145 @@ pub enum NetworkMessage {
     DamagePlayer(Damage),
     ScoreUpdate(Score),
     SyncClient(SyncMessage),
+    PaymentUpdate(PaymentUpdate),
+    RunSummary(Box<RunSummary>),
+    EntryInvoice(EntryInvoice),
+    Challenge(Challenge),
+    SeedUpdate(SeedUpdate),
+    ObjectSpawns(ObjectMsg),
+    IdleWarning(u64),
+    Disconnect(DisconnectReason),
+    Error { code: ErrorCode, message: String },
+    LoginChallenge(LoginChallenge),
+    LoggedIn(LoggedIn),
+    HighScores(Vec<HighScore>),
+    NewSeason(Season),
+    EnteredTop(TopScore),
+}
+
+impl NetworkMessage {
+    // what clients from before `ws::PROTOCOL_VERSION` are sent instead, the rest only go to
+    // clients that asked for them
+    pub fn legacy(&self) -> Option<LegacyMessage> {
+        let legacy = match self {
+            NetworkMessage::GameUpdate(positions) => LegacyMessage::GameUpdate(positions.clone()),
+            NetworkMessage::GameState(players) => {
+                LegacyMessage::GameState(players.iter().map(LegacyPlayerState::from).collect())
+            }
+            NetworkMessage::NewGame(new_game) => LegacyMessage::NewGame(new_game.into()),
+            NetworkMessage::Ping => LegacyMessage::Ping,
+            NetworkMessage::DamagePlayer(damage) => LegacyMessage::DamagePlayer(damage.into()),
+            NetworkMessage::ScoreUpdate(score) => LegacyMessage::ScoreUpdate(score.clone()),
+            NetworkMessage::SyncClient(sync) => LegacyMessage::SyncClient(sync.clone()),
+            _ => return None,
+        };
+
+        Some(legacy)
+    }
+}
+
+// the protocol as it was before `ws::PROTOCOL_VERSION`, variants in the same order so the tags
+// match, with high scores as (name, seconds) and run times in whole seconds
+#[derive(Readable, Writable, Debug, Clone)]
+pub enum LegacyMessage {
+    GameUpdate(Vec<NewPos>),
+    GameState(Vec<LegacyPlayerState>),
+    NewGame(LegacyNewGame),
+    Ping,
+    DamagePlayer(LegacyDamage),
+    ScoreUpdate(Score),
+    SyncClient(SyncMessage),
+}
+
+fn legacy_high_scores(high_scores: &[HighScore]) -> Vec<(String, u64)> {
+    high_scores
+        .iter()
+        .map(|score| (score.name.clone(), score.millis / 1000))
+        .collect()
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct LegacyNewGame {
+    pub id: Uuid,
+    pub server_tick: u64,
+    pub rng_seed: u64,
+    pub high_scores: Vec<(String, u64)>,
+    pub objects: ObjectMsg,
+}
+
+impl From<&NewGame> for LegacyNewGame {
+    fn from(new_game: &NewGame) -> Self {
+        Self {
+            id: new_game.id,
+            server_tick: new_game.server_tick,
+            rng_seed: new_game.rng_seed,
+            high_scores: legacy_high_scores(&new_game.high_scores),
+            objects: new_game.objects.clone(),
+        }
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct LegacyPlayerState {
+    pub pos: [f32; 2],
+    pub target: [f32; 2],
+    pub score: usize,
+    pub name: Option<String>,
+    pub id: Uuid,
+    pub time_alive: u64,
+    pub alive: bool,
+}
+
+impl From<&PlayerState> for LegacyPlayerState {
+    fn from(state: &PlayerState) -> Self {
+        Self {
+            pos: state.pos,
+            target: state.target,
+            score: state.score,
+            name: state.name.clone(),
+            id: state.id,
+            time_alive: state.millis_alive / 1000,
+            alive: state.alive,
+        }
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct LegacyDamage {
+    pub id: Uuid,
+    pub tick: Option<u64>,
+    pub secs_alive: u64,
+    pub high_scores: Option<Vec<(String, u64)>>,
+    pub pos: [f32; 2],
+    pub score: usize,
+}
+
+impl From<&Damage> for LegacyDamage {
+    fn from(damage: &Damage) -> Self {
+        Self {
+            id: damage.id,
+            tick: damage.tick,
+            secs_alive: damage.millis_alive / 1000,
+            high_scores: damage.high_scores.as_deref().map(legacy_high_scores),
+            pos: damage.pos,
+            score: damage.score,
+        }
+    }
 }
 
 #[derive(Readable, Writable, Debug, Clone)]
 pub enum ClientMessage {
     PlayerInput(PlayerInput),
+    // legacy, a name that is also an LN address is taken as the payout address
     PlayerName(String),
+    Profile(Profile),
+    ChallengeSolution(u64),
+    Resume(Uuid),
+    Heartbeat,
+    // asks for an LNURL-auth challenge to scan with a wallet
+    Login,
+    // a session token from `LoggedIn`, or any other issuer, for the current connection
+    Authenticate(String),
 }
 
 #[derive(Readable, Writable, Debug, Clone, Default)]
@@ -66,25 +200,32 @@ pub struct PlayerInput {
 pub struct NewGame {
     pub id: Uuid,
     pub server_tick: u64,
+    // 0 when `seed_hidden`, kept a plain u64 so deterministic clients still decode it
     pub rng_seed: u64,
-    pub high_scores: Vec<(String, u64)>,
+    pub high_scores: Vec<HighScore>,
     pub objects: ObjectMsg,
+    pub resume_token: Uuid,
+    // new fields go after the existing ones, older clients ignore trailing bytes
+    pub seed_hidden: bool,
 }
 
 impl NewGame {
     pub fn new(
         id: Uuid,
         server_tick: u64,
-        rng_seed: u64,
-        high_scores: Vec<(String, u64)>,
+        rng_seed: Option<u64>,
+        high_scores: Vec<HighScore>,
         objects: ObjectMsg,
+        resume_token: Uuid,
     ) -> Self {
         Self {
             id,
             server_tick,
-            rng_seed,
+            rng_seed: rng_seed.unwrap_or(0),
             high_scores,
             objects,
+            resume_token,
+            seed_hidden: rng_seed.is_none(),
         }
     }
 }
@@ -108,7 +249,7 @@ pub struct PlayerState {
     pub score: usize,
     pub name: Option<String>,
     pub id: Uuid,
-    pub time_alive: u64,
+    pub millis_alive: u64,
     pub alive: bool,
 }
 
@@ -119,7 +260,7 @@ impl PlayerState {
         score: usize,
         name: Option<String>,
         id: Uuid,
-        time_alive: u64,
+        millis_alive: u64,
         alive: bool,
     ) -> Self {
         Self {
@@ -128,18 +269,66 @@ impl PlayerState {
             score,
             name,
             id,
-            time_alive,
+            millis_alive,
             alive,
         }
     }
 }
 
+#[derive(Readable, Writable, Serialize, Deserialize, Debug, Clone)]
+pub struct HighScore {
+    // the run's player id, `account` is the persistent identity when they were logged in
+    pub player_id: Uuid,
+    pub account: Option<String>,
+    pub name: String,
+    // server ticks from spawn to finish, what the ranking goes by
+    pub ticks: u64,
+    pub millis: u64,
+    // unix seconds
+    pub set_at: u64,
+    pub room: String,
+    pub seed_commitment: String,
+    pub ln_verified: bool,
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct TopScore {
+    pub rank: u64,
+    pub score: HighScore,
+}
+
+impl TopScore {
+    pub fn new(rank: u64, score: HighScore) -> Self {
+        Self { rank, score }
+    }
+}
+
+// where a finished run leaves the player on the season's board
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct RunRank {
+    // 1 is first place
+    pub rank: u64,
+    pub previous_best_millis: Option<u64>,
+    // this run minus the previous best, negative when it was faster
+    pub difference_millis: Option<i64>,
+    pub personal_best: bool,
+    pub top: bool,
+}
+
+#[derive(Readable, Writable, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Season {
+    pub id: u64,
+    // unix seconds, a season without an end runs until it's rolled over by hand
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
 #[derive(Readable, Writable, Debug, Clone)]
 pub struct Damage {
     pub id: Uuid,
     pub tick: Option<u64>,
-    pub secs_alive: u64,
-    pub high_scores: Option<Vec<(String, u64)>>,
+    pub millis_alive: u64,
+    pub high_scores: Option<Vec<HighScore>>,
     pub pos: [f32; 2],
     pub score: usize,
 }
@@ -148,15 +337,15 @@ impl Damage {
     pub fn new(
         id: Uuid,
         tick: Option<u64>,
-        secs_alive: u64,
-        high_scores: Option<Vec<(String, u64)>>,
+        millis_alive: u64,
+        high_scores: Option<Vec<HighScore>>,
         pos: [f32; 2],
         score: usize,
     ) -> Self {
         Self {
             id,
             tick,
-            secs_alive,
+            millis_alive,
             high_scores,
             pos,
             score,
@@ -176,3 +365,250 @@ impl Score {
         Self { id, score, tick }
     }
 }
+
+#[derive(Readable, Writable, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    HeartbeatTimeout,
+    Idle,
+    ProtocolViolation,
+    Replaced,
+    Unavailable,
+    Banned,
+}
+
+impl DisconnectReason {
+    // application close codes sit in the 4000-4999 range
+    pub fn close_code(&self) -> u16 {
+        match self {
+            DisconnectReason::HeartbeatTimeout => 4000,
+            DisconnectReason::Idle => 4001,
+            DisconnectReason::ProtocolViolation => 4002,
+            DisconnectReason::Replaced => 4003,
+            DisconnectReason::Unavailable => 4004,
+            DisconnectReason::Banned => 4005,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            DisconnectReason::HeartbeatTimeout => "no heartbeat received",
+            DisconnectReason::Idle => "kicked for being idle",
+            DisconnectReason::ProtocolViolation => "too many invalid messages",
+            DisconnectReason::Replaced => "session resumed on another connection",
+            DisconnectReason::Unavailable => "server is not ready",
+            DisconnectReason::Banned => "banned",
+        }
+    }
+}
+
+// non-fatal problems, the connection stays open
+#[derive(Readable, Writable, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    InvalidMessage,
+    UnsupportedFrame,
+    InvalidLnAddress,
+    InvalidChallenge,
+    InvalidResumeToken,
+    InvalidSessionToken,
+    NameTaken,
+    InvalidName,
+    Banned,
+    LoginUnavailable,
+    EntryNeedsLnAddress,
+}
+
+#[derive(Readable, Writable, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentStatus {
+    Pending,
+    Sent,
+    Failed,
+    Capped,
+    Review,
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct PaymentUpdate {
+    pub id: Uuid,
+    pub amount: u64,
+    pub status: PaymentStatus,
+    pub reference: Option<String>,
+}
+
+impl PaymentUpdate {
+    pub fn new(id: Uuid, amount: u64, status: PaymentStatus, reference: Option<String>) -> Self {
+        Self {
+            id,
+            amount,
+            status,
+            reference,
+        }
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct RunSummary {
+    pub id: Uuid,
+    pub score: usize,
+    pub millis_alive: u64,
+    pub payments: Vec<PaymentUpdate>,
+    pub claim: Option<WithdrawClaim>,
+    pub result: Option<SignedRunResult>,
+    // only for finished runs
+    pub rank: Option<RunRank>,
+}
+
+impl RunSummary {
+    pub fn new(
+        id: Uuid,
+        score: usize,
+        millis_alive: u64,
+        payments: Vec<PaymentUpdate>,
+        claim: Option<WithdrawClaim>,
+        result: Option<SignedRunResult>,
+        rank: Option<RunRank>,
+    ) -> Self {
+        Self {
+            id,
+            score,
+            millis_alive,
+            payments,
+            claim,
+            result,
+            rank,
+        }
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct RunResult {
+    pub id: Uuid,
+    pub player_id: Uuid,
+    pub name: String,
+    pub account: Option<String>,
+    pub room: String,
+    pub seed: Option<u64>,
+    pub seed_commitment: String,
+    pub start_tick: u64,
+    pub end_tick: u64,
+    pub bolts: usize,
+    pub millis: u64,
+    pub msats_paid: u64,
+    pub finished: bool,
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct SignedRunResult {
+    pub result: RunResult,
+    pub signature: Vec<u8>,
+    pub proof: String,
+}
+
+impl SignedRunResult {
+    pub fn new(result: RunResult, signature: Vec<u8>, proof: String) -> Self {
+        Self {
+            result,
+            signature,
+            proof,
+        }
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct WithdrawClaim {
+    pub amount: u64,
+    pub lnurl: String,
+    pub qr: String,
+}
+
+impl WithdrawClaim {
+    pub fn new(amount: u64, lnurl: String, qr: String) -> Self {
+        Self { amount, lnurl, qr }
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct Profile {
+    pub display_name: String,
+    // only used for payouts, never shown to anyone
+    pub ln_address: Option<String>,
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct LoginChallenge {
+    pub lnurl: String,
+    pub qr: String,
+}
+
+impl LoginChallenge {
+    pub fn new(lnurl: String, qr: String) -> Self {
+        Self { lnurl, qr }
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct LoggedIn {
+    pub account: String,
+    pub token: String,
+}
+
+impl LoggedIn {
+    pub fn new(account: String, token: String) -> Self {
+        Self { account, token }
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct EntryInvoice {
+    pub invoice: String,
+    pub amount: u64,
+    pub expires_in: u64,
+}
+
+impl EntryInvoice {
+    pub fn new(invoice: String, amount: u64, expires_in: u64) -> Self {
+        Self {
+            invoice,
+            amount,
+            expires_in,
+        }
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct Challenge {
+    pub prefix: String,
+    pub difficulty: u8,
+}
+
+impl Challenge {
+    pub fn new(prefix: String, difficulty: u8) -> Self {
+        Self { prefix, difficulty }
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone)]
+pub struct SeedUpdate {
+    pub rng_seed: Option<u64>,
+    pub start_tick: u64,
+    pub commitment: String,
+    pub entropy: String,
+    pub next_commitment: String,
+}
+
+impl SeedUpdate {
+    pub fn new(
+        rng_seed: Option<u64>,
+        start_tick: u64,
+        commitment: String,
+        entropy: String,
+        next_commitment: String,
+    ) -> Self {
+        Self {
+            rng_seed,
+            start_tick,
+            commitment,
+            entropy,
+            next_commitment,
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::{env, fs};
+
+use log::{error, info};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use zebedee_rust::ln_address::LnAddress;
+
+use crate::Server;
+
+// invisible and direction-changing characters that let two names look the same
+const INVISIBLE: &[char] = &[
+    '\u{200B}', '\u{200C}', '\u{200D}', '\u{200E}', '\u{200F}', '\u{202A}', '\u{202B}', '\u{202C}',
+    '\u{202D}', '\u{202E}', '\u{2060}', '\u{2061}', '\u{2062}', '\u{2063}', '\u{2064}', '\u{FEFF}',
+];
+
+const PUNCTUATION: &[char] = &[' ', '-', '_', '.', '!', '?', '\''];
+
+// names players could use to pass themselves off as the operators
+const RESERVED: &[&str] = &["admin", "moderator", "satrunner", "server", "system"];
+
+#[derive(Debug, Clone)]
+pub struct NameRules {
+    pub min_chars: usize,
+    pub max_chars: usize,
+    pub blocklist: Vec<String>,
+}
+
+impl NameRules {
+    // NAME_BLOCKLIST is comma separated, NAME_BLOCKLIST_FILE has one entry per line
+    pub fn from_env() -> Self {
+        let min_chars = env::var("DISPLAY_NAME_MIN_CHARS")
+            .ok()
+            .and_then(|chars| chars.parse().ok())
+            .unwrap_or(2);
+
+        let max_chars = env::var("DISPLAY_NAME_MAX_CHARS")
+            .ok()
+            .and_then(|chars| chars.parse().ok())
+            .unwrap_or(16);
+
+        let mut blocklist: Vec<String> = RESERVED.iter().map(|word| word.to_string()).collect();
+
+        if let Ok(words) = env::var("NAME_BLOCKLIST") {
+            blocklist.extend(words.split(',').map(str::to_string));
+        }
+
+        if let Ok(path) = env::var("NAME_BLOCKLIST_FILE") {
+            match fs::read_to_string(&path) {
+                Ok(words) => blocklist.extend(words.lines().map(str::to_string)),
+                Err(e) => error!("Failed to read name blocklist {}: {}", path, e),
+            }
+        }
+
+        let mut blocklist: Vec<String> = blocklist
+            .iter()
+            .map(|word| fold(word))
+            .filter(|word| !word.is_empty())
+            .collect();
+        blocklist.sort();
+        blocklist.dedup();
+
+        info!("{} blocked name entries", blocklist.len());
+
+        Self {
+            min_chars,
+            max_chars,
+            blocklist,
+        }
+    }
+
+    // returns the name with its whitespace collapsed
+    pub fn validate(&self, name: &str) -> Result<String, String> {
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        let chars = name.chars().count();
+
+        if chars < self.min_chars || chars > self.max_chars {
+            return Err(format!(
+                "names must be {} to {} characters",
+                self.min_chars, self.max_chars
+            ));
+        }
+
+        if name.chars().any(|c| {
+            c.is_control()
+                || INVISIBLE.contains(&c)
+                || !(c.is_alphanumeric() || PUNCTUATION.contains(&c))
+        }) {
+            return Err("names can only use letters, numbers and - _ . ! ? '".to_string());
+        }
+
+        let folded = fold(&name);
+
+        if folded.is_empty() {
+            return Err("names need at least one letter or number".to_string());
+        }
+
+        if self.blocked(&name) {
+            return Err("that name isn't allowed".to_string());
+        }
+
+        Ok(name)
+    }
+
+    // an entry has to be the whole name or one of its words, so "observer" and "badminton" are
+    // fine while "5erver" and "admin bob" aren't
+    fn blocked(&self, name: &str) -> bool {
+        let words = name.split(|c: char| PUNCTUATION.contains(&c)).map(fold);
+
+        std::iter::once(fold(name))
+            .chain(words)
+            .any(|word| self.blocklist.contains(&word))
+    }
+}
+
+// lower case, common digit swaps undone and everything but letters and numbers dropped, so
+// "B.o.b" and "b0b" are the same name
+pub fn fold(name: &str) -> String {
+    name.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            '0' => 'o',
+            '1' => 'i',
+            '3' => 'e',
+            '4' => 'a',
+            '5' => 's',
+            '7' => 't',
+            '@' => 'a',
+            '$' => 's',
+            c => c,
+        })
+        .filter(|c| c.is_alphanumeric())
+        .collect()
+}
+
+pub fn is_ln_address(name: &str) -> bool {
+    LnAddress {
+        address: name.to_string(),
+    }
+    .validate()
+    .is_ok()
+}
+
+// for clients that still send their LN address as their name, stable so the leaderboard keeps
+// them together without publishing the address
+pub fn anonymous_name(ln_address: &str) -> String {
+    let digest = Sha256::digest(ln_address.trim().to_lowercase().as_bytes());
+    format!("runner-{}", hex::encode(&digest[..3]))
+}
+
+// display names are unique among the players in the room, case and punctuation aside
+pub async fn reserve(server: &Server, client_id: Uuid, name: &str) -> Result<(), String> {
+    let key = fold(name);
+    let mut display_names = server.display_names.lock().await;
+
+    match display_names.get(&key) {
+        Some(holder) if *holder != client_id => Err(format!("{} is already playing", name)),
+        _ => {
+            display_names.retain(|_, holder| *holder != client_id);
+            display_names.insert(key, client_id);
+            Ok(())
+        }
+    }
+}
+
+pub async fn release(server: &Server, client_id: Uuid) {
+    server
+        .display_names
+        .lock()
+        .await
+        .retain(|_, holder| *holder != client_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> NameRules {
+        NameRules {
+            min_chars: 2,
+            max_chars: 16,
+            blocklist: RESERVED.iter().map(|word| word.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn fold_undoes_lookalikes() {
+        assert_eq!(fold("B.o.b"), "bob");
+        assert_eq!(fold("b0b"), "bob");
+        assert_eq!(fold("5erver"), "server");
+        assert_eq!(fold("R4in_Runner 7"), "rainrunnert");
+        assert_eq!(fold("--"), "");
+    }
+
+    #[test]
+    fn validate_blocks_whole_names_and_words() {
+        let rules = rules();
+
+        assert_eq!(
+            rules.validate("  bob   the  runner ").unwrap(),
+            "bob the runner"
+        );
+        assert!(rules.validate("observer").is_ok());
+        assert!(rules.validate("badminton").is_ok());
+
+        assert!(rules.validate("Server").is_err());
+        assert!(rules.validate("5erver").is_err());
+        assert!(rules.validate("s.y.s.t.e.m").is_err());
+        assert!(rules.validate("admin bob").is_err());
+        assert!(rules.validate("bob_admin").is_err());
+
+        assert!(rules.validate("b").is_err());
+        assert!(rules.validate("a name far too long").is_err());
+        assert!(rules.validate("bob\u{200B}").is_err());
+        assert!(rules.validate("b@b").is_err());
+        assert!(rules.validate("...").is_err());
+    }
+
+    #[tokio::test]
+    async fn reserve_keeps_names_unique() {
+        let server = Server::default();
+        let bob = Uuid::new_v4();
+        let other = Uuid::new_v4();
+
+        assert!(reserve(&server, bob, "Bob").await.is_ok());
+        assert!(reserve(&server, other, "b.0.b").await.is_err());
+        assert!(reserve(&server, bob, "bob").await.is_ok());
+
+        // taking a new name frees the old one
+        assert!(reserve(&server, bob, "alice").await.is_ok());
+        assert!(reserve(&server, other, "bob").await.is_ok());
+
+        release(&server, bob).await;
+        assert!(reserve(&server, other, "alice").await.is_ok());
+        assert_eq!(server.display_names.lock().await.len(), 1);
+    }
+}
@@ -0,0 +1,499 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{error, info, warn};
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::{
+    anticheat::flagged,
+    bans::{check, BanKind},
+    challenge::record_volume,
+    events::{self, Event},
+    game_loop::PlayerEntity,
+    messages::{NetworkMessage, PaymentStatus, PaymentUpdate},
+    Server,
+};
+
+// how long a run summary waits for the run's payments before it's sent with them still pending
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(30);
+const RESOLVE_POLL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayoutMode {
+    PerBolt,
+    Settlement,
+}
+
+#[derive(Debug, Clone)]
+pub struct PayoutConfig {
+    pub mode: PayoutMode,
+    pub threshold_msats: u64,
+    pub partial_after: Option<Duration>,
+    pub run_cap_msats: Option<u64>,
+}
+
+impl PayoutConfig {
+    pub fn from_env() -> Self {
+        let mode = match env::var("PAYOUT_MODE").as_deref() {
+            Ok("settlement") => PayoutMode::Settlement,
+            _ => PayoutMode::PerBolt,
+        };
+
+        let threshold_msats = env::var("SETTLEMENT_THRESHOLD_MSATS")
+            .ok()
+            .and_then(|threshold| threshold.parse().ok())
+            .unwrap_or(0);
+
+        let partial_after = env::var("SETTLEMENT_PARTIAL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs);
+
+        let run_cap_msats = env::var("PAYOUT_RUN_CAP_MSATS")
+            .ok()
+            .and_then(|cap| cap.parse().ok());
+
+        Self {
+            mode,
+            threshold_msats,
+            partial_after,
+            run_cap_msats,
+        }
+    }
+}
+
+// pays straight away in per-bolt mode, otherwise adds to the run's unsettled balance. players
+// without a lightning address build up a balance they can claim over LNURL-withdraw
+pub async fn credit(server: &Arc<Server>, player: &mut PlayerEntity, amount: u64) {
+    if amount == 0 || (player.ln_address.is_none() && !server.lnurl.withdraw_enabled) {
+        return;
+    }
+
+    let allowed = match server.payouts.run_cap_msats {
+        Some(cap) => amount.min(cap.saturating_sub(player.credited)),
+        None => amount,
+    };
+
+    if allowed < amount {
+        let capped = PaymentUpdate::new(
+            Uuid::new_v4(),
+            amount - allowed,
+            PaymentStatus::Capped,
+            None,
+        );
+        record_payment(server, player.id, capped).await;
+    }
+
+    player.credited += allowed;
+
+    record_volume(server, player.ln_address.as_deref(), player.ip, allowed).await;
+
+    if player.ln_address.is_none() {
+        player.claimable += allowed;
+        return;
+    }
+
+    match server.payouts.mode {
+        PayoutMode::PerBolt => payout(server, player, allowed).await,
+        PayoutMode::Settlement => player.earned += allowed,
+    }
+}
+
+// at the end of a run anything under the threshold is carried over to the player's next run,
+// partial settlements just leave it on the player
+pub async fn settle(server: &Arc<Server>, player: &mut PlayerEntity, end_of_run: bool) {
+    let ln_address = match &player.ln_address {
+        Some(ln_address) if server.payouts.mode == PayoutMode::Settlement => ln_address.clone(),
+        _ => return,
+    };
+
+    // carried over per payout address, so it follows the player across display names
+    let mut carry_over = server.carry_over.lock().await;
+    let carried = carry_over.remove(&ln_address);
+    let total = player.earned + carried.unwrap_or(0);
+
+    player.earned = 0;
+    player.last_settlement = Instant::now();
+
+    if total == 0 {
+        return;
+    }
+
+    if total >= server.payouts.threshold_msats {
+        info!("Settling {} msats for {:?}", total, player.name);
+        payout(server, player, total).await;
+    } else if end_of_run {
+        info!("Carrying over {} msats for {:?}", total, player.name);
+        carry_over.insert(ln_address.clone(), total);
+    } else {
+        player.earned = total;
+    }
+
+    if carried.is_some() || carry_over.contains_key(&ln_address) {
+        store_carry_over(server, &ln_address, carry_over.get(&ln_address).copied()).await;
+    }
+}
+
+fn carry_over_key(room: &str) -> String {
+    format!("carry_over:{}", room)
+}
+
+// carried over balances are owed to players, so they have to survive a restart
+async fn store_carry_over(server: &Server, ln_address: &str, amount: Option<u64>) {
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        let key = carry_over_key(&server.room);
+
+        let stored: redis::RedisResult<()> = match amount {
+            Some(amount) => redis_client.hset(key, ln_address, amount),
+            None => redis_client.hdel(key, ln_address),
+        };
+
+        if let Err(e) = stored {
+            error!("Failed to store carry over: {}", e);
+        }
+    }
+}
+
+pub async fn load_carry_over(server: &Server) {
+    let carry_over: HashMap<String, u64> = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => redis_client
+            .hgetall(carry_over_key(&server.room))
+            .unwrap_or_else(|e| {
+                error!("Failed to load carry over: {}", e);
+                HashMap::new()
+            }),
+        None => return,
+    };
+
+    info!("Loaded carry over for {} addresses", carry_over.len());
+    server.carry_over.lock().await.extend(carry_over);
+}
+
+pub fn settlement_due(server: &Server, player: &PlayerEntity) -> bool {
+    match server.payouts.partial_after {
+        Some(partial_after) => {
+            server.payouts.mode == PayoutMode::Settlement
+                && player.earned > 0
+                && player.last_settlement.elapsed() >= partial_after
+        }
+        None => false,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewItem {
+    pub id: Uuid,
+    pub player_id: Uuid,
+    pub name: String,
+    // reviews stored before names and addresses were split only have the address as the name
+    #[serde(default)]
+    pub ln_address: String,
+    pub amount: u64,
+    pub reasons: Vec<String>,
+    pub created: u64,
+}
+
+// flagged runs keep playing but their payouts wait for an admin to approve or reject them
+async fn payout(server: &Arc<Server>, player: &PlayerEntity, amount: u64) {
+    let ln_address = match &player.ln_address {
+        Some(ln_address) => ln_address.clone(),
+        None => return,
+    };
+
+    match flagged(player, &server.anticheat) {
+        Some(bot_score) => {
+            hold_for_review(server, player, ln_address, amount, bot_score.reasons).await
+        }
+        None => pay_ln_address(server.clone(), player.id, ln_address, amount).await,
+    }
+}
+
+async fn hold_for_review(
+    server: &Server,
+    player: &PlayerEntity,
+    ln_address: String,
+    amount: u64,
+    reasons: Vec<String>,
+) {
+    if amount == 0 {
+        return;
+    }
+
+    let review = ReviewItem {
+        id: Uuid::new_v4(),
+        player_id: player.id,
+        name: player.name.clone(),
+        ln_address,
+        amount,
+        reasons,
+        created: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0),
+    };
+
+    warn!(
+        "Holding {} msats for {:?} for review: {:?}",
+        amount, player.name, review.reasons
+    );
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        match serde_json::to_string(&review) {
+            Ok(json) => {
+                let _: () = redis_client
+                    .hset("payout_reviews", review.id.to_string(), json)
+                    .unwrap_or_else(|e| error!("Failed to store review: {}", e));
+            }
+            Err(e) => error!("Failed to serialize review: {}", e),
+        }
+    }
+
+    let update = PaymentUpdate::new(review.id, amount, PaymentStatus::Review, None);
+    server.review_queue.lock().await.insert(review.id, review);
+    record_payment(server, player.id, update).await;
+}
+
+pub async fn remove_review(server: &Server, id: Uuid) -> Option<ReviewItem> {
+    let review = server.review_queue.lock().await.remove(&id)?;
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        let _: () = redis_client
+            .hdel("payout_reviews", id.to_string())
+            .unwrap_or_else(|e| error!("Failed to remove review: {}", e));
+    }
+
+    Some(review)
+}
+
+pub async fn load_reviews(server: &Server) {
+    let reviews: Vec<ReviewItem> = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => redis_client
+            .hvals::<_, Vec<String>>("payout_reviews")
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|json| serde_json::from_str(json).ok())
+            .collect(),
+        None => return,
+    };
+
+    info!("Loaded {} payouts awaiting review", reviews.len());
+
+    let mut review_queue = server.review_queue.lock().await;
+    for review in reviews {
+        review_queue.insert(review.id, review);
+    }
+}
+
+pub async fn pay_ln_address(server: Arc<Server>, player_id: Uuid, ln_address: String, amount: u64) {
+    if amount == 0 {
+        return;
+    }
+
+    let pending = PaymentUpdate::new(Uuid::new_v4(), amount, PaymentStatus::Pending, None);
+    record_payment(&server, player_id, pending.clone()).await;
+
+    if let Some(ban) = check(&server, BanKind::LnAddress, &ln_address).await {
+        warn!(
+            "Not paying banned address for {}: {}",
+            player_id, ban.reason
+        );
+        let failed = PaymentUpdate {
+            status: PaymentStatus::Failed,
+            ..pending
+        };
+        record_payment(&server, player_id, failed).await;
+        return;
+    }
+
+    tokio::spawn(async move {
+        let payment_response = server
+            .payment_provider
+            .pay_ln_address(&ln_address, amount, &server.rewards.comment)
+            .await;
+
+        let update = match payment_response {
+            Ok(receipt) => PaymentUpdate {
+                status: PaymentStatus::Sent,
+                reference: receipt.reference,
+                ..pending
+            },
+            Err(e) => {
+                info!("Payment failed {:?}", e);
+                PaymentUpdate {
+                    status: PaymentStatus::Failed,
+                    ..pending
+                }
+            }
+        };
+
+        record_payment(&server, player_id, update).await;
+    });
+}
+
+// stops collecting payments for the player's run, the pending ones are still followed by id so
+// `resolve_payments` can wait for them. Taken under the run_payments lock so no update is lost
+// in between
+pub async fn end_run_payments(server: &Server, player_id: Uuid) -> Vec<PaymentUpdate> {
+    let mut run_payments = server.run_payments.lock().await;
+    let payments = run_payments.remove(&player_id).unwrap_or_default();
+
+    let mut unresolved = server.unresolved_payments.lock().await;
+    for payment in &payments {
+        if payment.status == PaymentStatus::Pending {
+            unresolved.insert(payment.id, payment.clone());
+        }
+    }
+
+    payments
+}
+
+// the latest status of each payment, once none are pending or the wait is over
+pub async fn resolve_payments(
+    server: &Server,
+    mut payments: Vec<PaymentUpdate>,
+) -> Vec<PaymentUpdate> {
+    let started = Instant::now();
+
+    loop {
+        let mut unresolved = server.unresolved_payments.lock().await;
+
+        for payment in payments.iter_mut() {
+            if let Some(latest) = unresolved.get(&payment.id) {
+                *payment = latest.clone();
+            }
+        }
+
+        let pending = payments
+            .iter()
+            .any(|payment| payment.status == PaymentStatus::Pending);
+
+        if !pending || started.elapsed() >= RESOLVE_TIMEOUT {
+            for payment in &payments {
+                unresolved.remove(&payment.id);
+            }
+            return payments;
+        }
+
+        drop(unresolved);
+        tokio::time::sleep(RESOLVE_POLL).await;
+    }
+}
+
+// keeps the latest status of each payment for the run summary and tells the player about it
+pub async fn record_payment(server: &Server, player_id: Uuid, update: PaymentUpdate) {
+    if update.status == PaymentStatus::Sent {
+        events::publish(
+            server,
+            Event::Payout {
+                amount: update.amount,
+            },
+        );
+    }
+
+    {
+        let mut run_payments = server.run_payments.lock().await;
+
+        match update.status {
+            PaymentStatus::Pending | PaymentStatus::Capped | PaymentStatus::Review => {
+                if let Some(payments) = run_payments.get_mut(&player_id) {
+                    payments.push(update.clone());
+                }
+            }
+            PaymentStatus::Sent | PaymentStatus::Failed => {
+                if let Some(payment) = run_payments
+                    .get_mut(&player_id)
+                    .and_then(|payments| payments.iter_mut().find(|p| p.id == update.id))
+                {
+                    *payment = update.clone();
+                } else if let Some(payment) =
+                    server.unresolved_payments.lock().await.get_mut(&update.id)
+                {
+                    *payment = update.clone();
+                }
+            }
+        }
+    }
+
+    let connections = server.connections.read().await;
+
+    if let Some(connection) = connections.get(&player_id) {
+        if let Err(e) = connection.send(NetworkMessage::PaymentUpdate(update)) {
+            error!("Failed to send message over WebSocket: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::MockProvider;
+
+    fn settlement_server() -> Arc<Server> {
+        Arc::new(Server {
+            payment_provider: Box::new(MockProvider::default()),
+            payouts: PayoutConfig {
+                mode: PayoutMode::Settlement,
+                threshold_msats: 1_000,
+                partial_after: None,
+                run_cap_msats: None,
+            },
+            ..Server::default()
+        })
+    }
+
+    async fn player(name: &str, ln_address: &str) -> PlayerEntity {
+        PlayerEntity::new(
+            Uuid::new_v4(),
+            name.to_string(),
+            Some(ln_address.to_string()),
+            None,
+            None,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn balances_under_the_threshold_carry_over() {
+        let server = settlement_server();
+        let ln_address = "runner@example.com";
+
+        let mut first = player("runner", ln_address).await;
+        credit(&server, &mut first, 400).await;
+        assert_eq!(first.earned, 400);
+
+        // a partial settlement leaves it on the player, only the end of the run carries it over
+        settle(&server, &mut first, false).await;
+        assert_eq!(first.earned, 400);
+        assert!(server.carry_over.lock().await.is_empty());
+
+        settle(&server, &mut first, true).await;
+        assert_eq!(first.earned, 0);
+        assert_eq!(server.carry_over.lock().await.get(ln_address), Some(&400));
+
+        // the next run is under another name but the same address, and crosses the threshold
+        let mut second = player("runner two", ln_address).await;
+        server
+            .run_payments
+            .lock()
+            .await
+            .insert(second.id, Vec::new());
+
+        credit(&server, &mut second, 700).await;
+        settle(&server, &mut second, true).await;
+
+        assert!(server.carry_over.lock().await.is_empty());
+        let paid: Vec<u64> = end_run_payments(&server, second.id)
+            .await
+            .iter()
+            .map(|payment| payment.amount)
+            .collect();
+        assert_eq!(paid, [1_100]);
+    }
+}
@@ -0,0 +1,310 @@
+use std::{collections::HashSet, env, fmt};
+
+use async_trait::async_trait;
+use log::info;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use zebedee_rust::{
+    charges::Charge,
+    ln_address::{LnAddress, LnPayment},
+    payments::Payment,
+    ZebedeeClient,
+};
+
+#[derive(Debug, Clone)]
+pub struct ProviderError(pub String);
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PaymentReceipt {
+    pub reference: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Invoice {
+    pub id: String,
+    pub request: String,
+}
+
+#[async_trait]
+pub trait PaymentProvider: Send + Sync {
+    async fn validate_ln_address(&self, ln_address: &str) -> Result<(), ProviderError>;
+
+    async fn pay_ln_address(
+        &self,
+        ln_address: &str,
+        amount: u64,
+        comment: &str,
+    ) -> Result<PaymentReceipt, ProviderError>;
+
+    async fn pay_invoice(
+        &self,
+        invoice: &str,
+        description: &str,
+    ) -> Result<PaymentReceipt, ProviderError>;
+
+    async fn create_invoice(
+        &self,
+        amount: u64,
+        description: &str,
+        expires_in: u32,
+    ) -> Result<Invoice, ProviderError>;
+
+    async fn invoice_paid(&self, invoice_id: &str) -> Result<bool, ProviderError>;
+}
+
+// PAYMENT_PROVIDER=mock swaps zebedee for the in-memory provider
+pub fn from_env() -> Box<dyn PaymentProvider> {
+    if env::var("PAYMENT_PROVIDER").as_deref() == Ok("mock") {
+        info!("Using mock payment provider");
+        return Box::new(MockProvider {
+            auto_pay: env::var("MOCK_AUTO_PAY").as_deref() == Ok("true"),
+            ..Default::default()
+        });
+    }
+
+    #[cfg(not(debug_assertions))]
+    let client = {
+        let api_key_json: String = env::var("ZBD_API_KEY").unwrap();
+        let value: Value = serde_json::from_str(&api_key_json).unwrap();
+
+        let api_key = value["ZBD_API_KEY"].as_str().unwrap().to_string();
+
+        ZebedeeClient::new().apikey(api_key).build()
+    };
+
+    #[cfg(debug_assertions)]
+    let client = ZebedeeClient::new().apikey("test".to_string()).build();
+
+    Box::new(ZebedeeProvider {
+        client: Mutex::new(client),
+    })
+}
+
+pub struct ZebedeeProvider {
+    client: Mutex<ZebedeeClient>,
+}
+
+#[async_trait]
+impl PaymentProvider for ZebedeeProvider {
+    async fn validate_ln_address(&self, ln_address: &str) -> Result<(), ProviderError> {
+        let ln_address = LnAddress {
+            address: ln_address.to_string(),
+        };
+
+        match self
+            .client
+            .lock()
+            .await
+            .validate_ln_address(&ln_address)
+            .await
+        {
+            Ok(res) => {
+                info!("Valid LN address: {:?}", res.data);
+                Ok(())
+            }
+            Err(e) => Err(ProviderError(e.to_string())),
+        }
+    }
+
+    async fn pay_ln_address(
+        &self,
+        ln_address: &str,
+        amount: u64,
+        comment: &str,
+    ) -> Result<PaymentReceipt, ProviderError> {
+        let payment = LnPayment {
+            ln_address: ln_address.to_string(),
+            amount: amount.to_string(),
+            comment: comment.to_string(),
+        };
+
+        match self.client.lock().await.pay_ln_address(&payment).await {
+            Ok(response) => {
+                info!("Payment sent to {:?}: {:?}", ln_address, response.data);
+                Ok(PaymentReceipt {
+                    reference: payment_reference(&response.data),
+                })
+            }
+            Err(e) => Err(ProviderError(e.to_string())),
+        }
+    }
+
+    async fn pay_invoice(
+        &self,
+        invoice: &str,
+        description: &str,
+    ) -> Result<PaymentReceipt, ProviderError> {
+        let payment = Payment {
+            description: description.to_string(),
+            internal_id: Uuid::new_v4().to_string(),
+            invoice: invoice.to_string(),
+        };
+
+        match self.client.lock().await.pay_invoice(&payment).await {
+            Ok(response) => {
+                info!("Invoice paid: {:?}", response.data);
+                Ok(PaymentReceipt {
+                    reference: payment_reference(&response.data),
+                })
+            }
+            Err(e) => Err(ProviderError(e.to_string())),
+        }
+    }
+
+    async fn create_invoice(
+        &self,
+        amount: u64,
+        description: &str,
+        expires_in: u32,
+    ) -> Result<Invoice, ProviderError> {
+        let charge = Charge {
+            expires_in,
+            amount: amount.to_string(),
+            description: description.to_string(),
+            internal_id: Uuid::new_v4().to_string(),
+            callback_url: String::new(),
+        };
+
+        match self.client.lock().await.create_charge(&charge).await {
+            Ok(response) => {
+                let data = serde_json::to_value(&response.data).unwrap_or_default();
+
+                match (data["id"].as_str(), data["invoice"]["request"].as_str()) {
+                    (Some(id), Some(request)) => Ok(Invoice {
+                        id: id.to_string(),
+                        request: request.to_string(),
+                    }),
+                    _ => Err(ProviderError(format!("Unexpected charge {:?}", data))),
+                }
+            }
+            Err(e) => Err(ProviderError(e.to_string())),
+        }
+    }
+
+    async fn invoice_paid(&self, invoice_id: &str) -> Result<bool, ProviderError> {
+        match self
+            .client
+            .lock()
+            .await
+            .get_charge(invoice_id.to_string())
+            .await
+        {
+            Ok(response) => {
+                let data = serde_json::to_value(&response.data).unwrap_or_default();
+                Ok(data["status"].as_str() == Some("completed"))
+            }
+            Err(e) => Err(ProviderError(e.to_string())),
+        }
+    }
+}
+
+fn payment_reference<T: Serialize>(data: &T) -> Option<String> {
+    let data = serde_json::to_value(data).ok()?;
+
+    data["preimage"]
+        .as_str()
+        .or_else(|| data["id"].as_str())
+        .map(String::from)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockPayment {
+    LnAddress { ln_address: String, amount: u64 },
+    Invoice { invoice: String },
+}
+
+// pays everything except addresses and invoices containing "fail". invoices it creates are
+// only paid once marked, or straight away with auto_pay
+#[derive(Default)]
+pub struct MockProvider {
+    pub payments: Mutex<Vec<MockPayment>>,
+    pub paid_invoices: Mutex<HashSet<String>>,
+    pub auto_pay: bool,
+}
+
+impl MockProvider {
+    pub async fn mark_paid(&self, invoice_id: &str) {
+        self.paid_invoices
+            .lock()
+            .await
+            .insert(invoice_id.to_string());
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for MockProvider {
+    async fn validate_ln_address(&self, ln_address: &str) -> Result<(), ProviderError> {
+        if ln_address.contains("fail") {
+            return Err(ProviderError(format!("invalid ln address {}", ln_address)));
+        }
+        Ok(())
+    }
+
+    async fn pay_ln_address(
+        &self,
+        ln_address: &str,
+        amount: u64,
+        _comment: &str,
+    ) -> Result<PaymentReceipt, ProviderError> {
+        if ln_address.contains("fail") {
+            return Err(ProviderError(format!("payment to {} failed", ln_address)));
+        }
+
+        self.payments.lock().await.push(MockPayment::LnAddress {
+            ln_address: ln_address.to_string(),
+            amount,
+        });
+
+        Ok(PaymentReceipt {
+            reference: Some(Uuid::new_v4().to_string()),
+        })
+    }
+
+    async fn pay_invoice(
+        &self,
+        invoice: &str,
+        _description: &str,
+    ) -> Result<PaymentReceipt, ProviderError> {
+        if invoice.contains("fail") {
+            return Err(ProviderError(format!("payment of {} failed", invoice)));
+        }
+
+        self.payments.lock().await.push(MockPayment::Invoice {
+            invoice: invoice.to_string(),
+        });
+
+        Ok(PaymentReceipt {
+            reference: Some(Uuid::new_v4().to_string()),
+        })
+    }
+
+    async fn create_invoice(
+        &self,
+        amount: u64,
+        _description: &str,
+        _expires_in: u32,
+    ) -> Result<Invoice, ProviderError> {
+        let id = Uuid::new_v4().to_string();
+
+        if self.auto_pay {
+            self.mark_paid(&id).await;
+        }
+
+        Ok(Invoice {
+            request: format!("lnmock{}n1{}", amount / 100, id.replace('-', "")),
+            id,
+        })
+    }
+
+    async fn invoice_paid(&self, invoice_id: &str) -> Result<bool, ProviderError> {
+        Ok(self.paid_invoices.lock().await.contains(invoice_id))
+    }
+}
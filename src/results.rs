@@ -0,0 +1,180 @@
+use std::{collections::HashMap, env, sync::Arc};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::{error, warn};
+use rand::Rng;
+use redis::Commands;
+use serde_json::json;
+use speedy::{Readable, Writable};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::{
+    messages::{RunResult, SignedRunResult},
+    Server,
+};
+
+const MAX_STORED_RESULTS: isize = 10_000;
+
+pub struct ResultSigner {
+    // none when there is no key, results then go out unsigned rather than under a key nobody
+    // can verify after a restart
+    signing_key: Option<SigningKey>,
+}
+
+impl ResultSigner {
+    // RESULT_SIGNING_KEY is the hex encoded 32 byte ed25519 secret key, RESULT_SIGNING_DEV=true
+    // signs with a throwaway key instead for local testing
+    pub fn from_env() -> Self {
+        let secret = env::var("RESULT_SIGNING_KEY")
+            .ok()
+            .and_then(|key| hex::decode(key.trim()).ok())
+            .and_then(|key| <[u8; 32]>::try_from(key).ok());
+
+        let secret = match secret {
+            Some(secret) => Some(secret),
+            None if env::var("RESULT_SIGNING_DEV").as_deref() == Ok("true") => {
+                warn!("No RESULT_SIGNING_KEY, results signed with a throwaway key");
+                Some(rand::thread_rng().gen())
+            }
+            None => {
+                error!("No valid RESULT_SIGNING_KEY, run results are not signed");
+                None
+            }
+        };
+
+        Self {
+            signing_key: secret.map(|secret| SigningKey::from_bytes(&secret)),
+        }
+    }
+
+    pub fn public_key(&self) -> Option<VerifyingKey> {
+        self.signing_key.as_ref().map(SigningKey::verifying_key)
+    }
+
+    pub fn sign(&self, result: RunResult) -> Option<SignedRunResult> {
+        let signing_key = self.signing_key.as_ref()?;
+
+        let payload = match result.write_to_vec() {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize run result: {}", e);
+                return None;
+            }
+        };
+
+        let signature = signing_key.sign(&payload).to_bytes().to_vec();
+        let proof = format!("{}.{}", hex::encode(&payload), hex::encode(&signature));
+
+        Some(SignedRunResult::new(result, signature, proof))
+    }
+}
+
+// proofs are "<hex speedy encoded result>.<hex signature>"
+pub fn verify_proof(public_key: &VerifyingKey, proof: &str) -> Result<RunResult, String> {
+    let (payload, signature) = proof
+        .trim()
+        .split_once('.')
+        .ok_or_else(|| "malformed proof".to_string())?;
+
+    let payload = hex::decode(payload).map_err(|e| format!("invalid payload: {}", e))?;
+    let signature = hex::decode(signature)
+        .ok()
+        .and_then(|signature| <[u8; 64]>::try_from(signature).ok())
+        .ok_or_else(|| "invalid signature".to_string())?;
+
+    public_key
+        .verify(&payload, &Signature::from_bytes(&signature))
+        .map_err(|_| "signature does not match".to_string())?;
+
+    RunResult::read_from_buffer(&payload).map_err(|e| format!("invalid result: {}", e))
+}
+
+pub async fn store_result(server: &Server, signed: &SignedRunResult) {
+    let key = format!("run_results:{}", server.room);
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        let _: () = redis_client
+            .rpush(&key, &signed.proof)
+            .and_then(|_: ()| redis_client.ltrim(&key, -MAX_STORED_RESULTS, -1))
+            .unwrap_or_else(|e| error!("Failed to store run result: {}", e));
+    }
+}
+
+fn unsigned() -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(
+        warp::reply::json(&json!({ "error": "results are not signed" })),
+        StatusCode::SERVICE_UNAVAILABLE,
+    )
+}
+
+pub fn routes(
+    server: Arc<Server>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let server = warp::any().map(move || server.clone());
+
+    let public_key = warp::path!("results" / "pubkey")
+        .and(warp::get())
+        .and(server.clone())
+        .map(
+            |server: Arc<Server>| match server.result_signer.public_key() {
+                Some(public_key) => warp::reply::with_status(
+                    warp::reply::json(&json!({
+                        "algorithm": "ed25519",
+                        "public_key": hex::encode(public_key.to_bytes()),
+                    })),
+                    StatusCode::OK,
+                ),
+                None => unsigned(),
+            },
+        );
+
+    let verify = warp::path!("results" / "verify")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(server)
+        .map(|query: HashMap<String, String>, server: Arc<Server>| {
+            let proof = match query.get("proof") {
+                Some(proof) => proof,
+                None => {
+                    return warp::reply::with_status(
+                        warp::reply::json(&json!({ "error": "proof required" })),
+                        StatusCode::BAD_REQUEST,
+                    )
+                }
+            };
+
+            let Some(public_key) = server.result_signer.public_key() else {
+                return unsigned();
+            };
+
+            match verify_proof(&public_key, proof) {
+                Ok(result) => warp::reply::with_status(
+                    warp::reply::json(&json!({
+                        "valid": true,
+                        "result": {
+                            "id": result.id,
+                            "player_id": result.player_id,
+                            "name": result.name,
+                            "account": result.account,
+                            "room": result.room,
+                            "seed": result.seed,
+                            "seed_commitment": result.seed_commitment,
+                            "start_tick": result.start_tick,
+                            "end_tick": result.end_tick,
+                            "bolts": result.bolts,
+                            "millis": result.millis,
+                            "msats_paid": result.msats_paid,
+                            "finished": result.finished,
+                        },
+                    })),
+                    StatusCode::OK,
+                ),
+                Err(reason) => warp::reply::with_status(
+                    warp::reply::json(&json!({ "valid": false, "reason": reason })),
+                    StatusCode::OK,
+                ),
+            }
+        });
+
+    public_key.or(verify)
+}
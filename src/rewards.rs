@@ -0,0 +1,182 @@
+use std::{collections::HashMap, env};
+
+use log::{error, info};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RewardSchedule {
+    pub bolt_msats: u64,
+    pub streak_step_msats: u64,
+    pub bolt_max_msats: u64,
+    pub milestones: Vec<Milestone>,
+    pub finish_bonus: Option<FinishBonus>,
+    pub comment: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Milestone {
+    pub score: usize,
+    pub msats: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FinishBonus {
+    pub msats: u64,
+    pub par_secs: u64,
+    #[serde(default)]
+    pub min_msats: u64,
+}
+
+impl Default for RewardSchedule {
+    fn default() -> Self {
+        Self {
+            bolt_msats: 1000,
+            streak_step_msats: 0,
+            bolt_max_msats: 1000,
+            milestones: vec![Milestone {
+                score: 21,
+                msats: 21000,
+            }],
+            finish_bonus: None,
+            comment: String::from("https://rain.run"),
+        }
+    }
+}
+
+impl RewardSchedule {
+    // REWARD_SCHEDULES is a JSON object of room name to schedule, REWARD_SCHEDULE a single
+    // schedule used for every room.
+    pub fn from_env(room: &str) -> Self {
+        if let Ok(schedules) = env::var("REWARD_SCHEDULES") {
+            match serde_json::from_str::<HashMap<String, RewardSchedule>>(&schedules) {
+                Ok(mut schedules) => {
+                    if let Some(schedule) = schedules.remove(room) {
+                        info!("Using reward schedule for room {}: {:?}", room, schedule);
+                        return schedule;
+                    }
+                }
+                Err(e) => error!("Invalid REWARD_SCHEDULES: {}", e),
+            }
+        }
+
+        if let Ok(schedule) = env::var("REWARD_SCHEDULE") {
+            match serde_json::from_str::<RewardSchedule>(&schedule) {
+                Ok(schedule) => {
+                    info!("Using reward schedule: {:?}", schedule);
+                    return schedule;
+                }
+                Err(e) => error!("Invalid REWARD_SCHEDULE: {}", e),
+            }
+        }
+
+        Self::default()
+    }
+
+    // amount for the bolt that brought the player to `score`, growing with the streak
+    pub fn bolt_reward(&self, score: usize) -> u64 {
+        let streak = score.saturating_sub(1) as u64;
+        let amount = self
+            .bolt_msats
+            .saturating_add(self.streak_step_msats.saturating_mul(streak));
+
+        amount.min(self.bolt_max_msats.max(self.bolt_msats))
+    }
+
+    pub fn milestone_reward(&self, score: usize) -> u64 {
+        self.milestones
+            .iter()
+            .filter(|milestone| milestone.score == score)
+            .map(|milestone| milestone.msats)
+            .sum()
+    }
+
+    // full bonus at or under par, scaled down the longer the run took
+    pub fn finish_reward(&self, millis: u64) -> u64 {
+        match &self.finish_bonus {
+            Some(bonus) => {
+                let par_millis = bonus.par_secs * 1000;
+
+                if millis <= par_millis {
+                    bonus.msats
+                } else {
+                    let scaled = bonus.msats as u128 * par_millis as u128 / millis as u128;
+                    (scaled as u64).max(bonus.min_msats)
+                }
+            }
+            None => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_schedule_pays_the_original_amounts() {
+        let schedule = RewardSchedule::default();
+
+        for score in 1..=50 {
+            assert_eq!(schedule.bolt_reward(score), 1000);
+        }
+        assert_eq!(schedule.milestone_reward(21), 21000);
+        assert_eq!(schedule.milestone_reward(20), 0);
+        assert_eq!(schedule.milestone_reward(42), 0);
+        assert_eq!(schedule.finish_reward(60_000), 0);
+    }
+
+    #[test]
+    fn finish_bonus_scales_past_par() {
+        let schedule = RewardSchedule {
+            finish_bonus: Some(FinishBonus {
+                msats: 10_000,
+                par_secs: 60,
+                min_msats: 2_000,
+            }),
+            ..RewardSchedule::default()
+        };
+
+        assert_eq!(schedule.finish_reward(30_000), 10_000);
+        assert_eq!(schedule.finish_reward(60_000), 10_000);
+        assert_eq!(schedule.finish_reward(120_000), 5_000);
+        assert_eq!(schedule.finish_reward(240_000), 2_500);
+        // never below the floor, however slow the run
+        assert_eq!(schedule.finish_reward(600_000), 2_000);
+        assert_eq!(schedule.finish_reward(u64::MAX), 2_000);
+    }
+
+    #[test]
+    fn schedules_are_picked_per_room() {
+        // the only test touching these variables
+        env::set_var(
+            "REWARD_SCHEDULES",
+            r#"{
+                "fast": {"bolt_msats": 2000, "streak_step_msats": 500, "bolt_max_msats": 4000},
+                "finish": {"milestones": [], "finish_bonus": {"msats": 5000, "par_secs": 30}}
+            }"#,
+        );
+        env::remove_var("REWARD_SCHEDULE");
+
+        let fast = RewardSchedule::from_env("fast");
+        assert_eq!(fast.bolt_reward(1), 2000);
+        assert_eq!(fast.bolt_reward(3), 3000);
+        assert_eq!(fast.bolt_reward(10), 4000);
+        // fields left out keep their defaults
+        assert_eq!(fast.milestone_reward(21), 21000);
+
+        let finish = RewardSchedule::from_env("finish");
+        assert_eq!(finish.bolt_reward(5), 1000);
+        assert_eq!(finish.milestone_reward(21), 0);
+        assert_eq!(finish.finish_reward(60_000), 2500);
+
+        let other = RewardSchedule::from_env("other");
+        assert_eq!(other.bolt_reward(5), 1000);
+        assert_eq!(other.milestone_reward(21), 21000);
+
+        env::set_var("REWARD_SCHEDULES", "not json");
+        assert_eq!(RewardSchedule::from_env("fast").bolt_reward(1), 1000);
+
+        env::remove_var("REWARD_SCHEDULES");
+    }
+}
@@ -0,0 +1,378 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::Arc,
+    time::Duration,
+};
+
+use log::{error, info, warn};
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::{
+    admin::with_admin_actor,
+    leaderboard::{self, all_entries, Board},
+    messages::{HighScore, NetworkMessage, Season},
+    payments::pay_ln_address,
+    Server,
+};
+
+const CURRENT_KEY: &str = "season_current";
+const ARCHIVE_KEY: &str = "season_archive";
+// payout addresses of everyone on a season's board, never part of the public records
+const PAYOUTS_KEY: &str = "season_payouts";
+
+#[derive(Debug, Clone)]
+pub struct SeasonConfig {
+    pub length: Option<Duration>,
+    pub prizes: Vec<u64>,
+}
+
+impl SeasonConfig {
+    pub fn from_env() -> Self {
+        let length = env::var("SEASON_DAYS")
+            .ok()
+            .and_then(|days| days.parse::<u64>().ok())
+            .filter(|days| *days > 0)
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60));
+
+        // msats for 1st, 2nd, 3rd... of the final standings, a typo would shift everyone's
+        // prize so none are paid instead
+        let prizes = env::var("SEASON_PRIZES_MSATS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|prize| !prize.is_empty())
+            .map(|prize| {
+                prize
+                    .parse::<u64>()
+                    .map_err(|e| format!("{:?}: {}", prize, e))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|e| {
+                error!("Invalid SEASON_PRIZES_MSATS, no season prizes: {}", e);
+                Vec::new()
+            });
+
+        Self { length, prizes }
+    }
+
+    fn end(&self, start: u64) -> Option<u64> {
+        self.length.map(|length| start + length.as_secs())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeasonPrize {
+    pub player_id: Uuid,
+    pub name: String,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeasonArchive {
+    pub season: Season,
+    // unix seconds the standings were archived, which can be later than the planned end
+    pub ended: u64,
+    pub standings: Vec<HighScore>,
+    pub prizes: Vec<SeasonPrize>,
+}
+
+fn payouts_key(id: u64) -> String {
+    format!("{}:{}", PAYOUTS_KEY, id)
+}
+
+pub async fn load_season(server: &Server) {
+    let now = server.clock.unix_secs();
+
+    let stored: Option<Season> = server.redis.lock().await.as_mut().and_then(|redis_client| {
+        redis_client
+            .get::<_, Option<String>>(CURRENT_KEY)
+            .ok()
+            .flatten()
+            .and_then(|json| serde_json::from_str(&json).ok())
+    });
+
+    let season = match stored {
+        // a length set since the season started applies from its start
+        Some(season) => Season {
+            end: season.end.or(server.seasons.end(season.start)),
+            ..season
+        },
+        None => Season {
+            id: 1,
+            start: now,
+            end: server.seasons.end(now),
+        },
+    };
+
+    info!("Season {}: {:?}", season.id, season);
+    store_season(server, &season).await;
+    *server.season.write().await = season;
+}
+
+async fn store_season(server: &Server, season: &Season) {
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        match serde_json::to_string(season) {
+            Ok(json) => {
+                let _: () = redis_client
+                    .set(CURRENT_KEY, json)
+                    .unwrap_or_else(|e| error!("Failed to store season: {}", e));
+            }
+            Err(e) => error!("Failed to serialize season: {}", e),
+        }
+    }
+}
+
+// remembered for the season prizes, flagged runs are left out
+pub async fn record_payout_address(server: &Server, player_id: Uuid, ln_address: &str) {
+    let id = server.season.read().await.id;
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        let _: () = redis_client
+            .hset(payouts_key(id), player_id.to_string(), ln_address)
+            .unwrap_or_else(|e| error!("Failed to store season payout address: {}", e));
+    }
+}
+
+// the players this season whose runs paid out to `ln_address`
+pub async fn players_paid_to(server: &Server, ln_address: &str) -> HashSet<Uuid> {
+    let id = server.season.read().await.id;
+
+    let addresses: HashMap<String, String> = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => redis_client.hgetall(payouts_key(id)).unwrap_or_default(),
+        None => HashMap::new(),
+    };
+
+    addresses
+        .into_iter()
+        .filter(|(_, address)| address.eq_ignore_ascii_case(ln_address))
+        .filter_map(|(player_id, _)| player_id.parse().ok())
+        .collect()
+}
+
+pub async fn check(server: &Arc<Server>) {
+    let end = server.season.read().await.end;
+
+    if end.is_some_and(|end| server.clock.unix_secs() >= end) {
+        rollover(server, "season").await;
+    }
+}
+
+// starts the next season, then archives the standings of the one that ended and pays its
+// prizes. Only the switch holds the season lock, finishes from then on count towards the next
+pub async fn rollover(server: &Arc<Server>, actor: &str) -> SeasonArchive {
+    let now = server.clock.unix_secs();
+
+    let (season, next) = {
+        let mut season = server.season.write().await;
+
+        // seasons keep their schedule unless the server was down for a whole season, one
+        // rolled over by hand starts now
+        let start = season
+            .end
+            .filter(|end| *end <= now)
+            .filter(|end| {
+                server
+                    .seasons
+                    .end(*end)
+                    .is_some_and(|next_end| now < next_end)
+            })
+            .unwrap_or(now);
+
+        let next = Season {
+            id: season.id + 1,
+            start,
+            end: server.seasons.end(start),
+        };
+
+        store_season(server, &next).await;
+        (std::mem::replace(&mut *season, next.clone()), next)
+    };
+
+    let board = Board::season(season.id);
+
+    let (standings, addresses): (Vec<HighScore>, HashMap<String, String>) =
+        match server.redis.lock().await.as_mut() {
+            Some(redis_client) => (
+                all_entries(redis_client, &board),
+                redis_client
+                    .hgetall(payouts_key(season.id))
+                    .unwrap_or_default(),
+            ),
+            None => (Vec::new(), HashMap::new()),
+        };
+
+    let mut prizes = Vec::new();
+
+    for (record, amount) in standings.iter().zip(server.seasons.prizes.iter()) {
+        match addresses.get(&record.player_id.to_string()) {
+            Some(ln_address) => {
+                info!(
+                    "Season {} prize for {:?}: {} msats",
+                    season.id, record.name, amount
+                );
+                pay_ln_address(
+                    server.clone(),
+                    record.player_id,
+                    ln_address.clone(),
+                    *amount,
+                )
+                .await;
+                prizes.push(SeasonPrize {
+                    player_id: record.player_id,
+                    name: record.name.clone(),
+                    amount: *amount,
+                });
+            }
+            None => warn!(
+                "No payout address for {:?}, season {} prize not paid",
+                record.name, season.id
+            ),
+        }
+    }
+
+    let archive = SeasonArchive {
+        season: season.clone(),
+        ended: now,
+        standings,
+        prizes,
+    };
+
+    if let Some(redis_client) = server.redis.lock().await.as_mut() {
+        match serde_json::to_string(&archive) {
+            Ok(json) => {
+                let mut pipe = redis::pipe();
+                pipe.atomic().hset(ARCHIVE_KEY, season.id, json);
+                board.delete(&mut pipe);
+                pipe.del(payouts_key(season.id));
+
+                let _: () = pipe
+                    .query(redis_client)
+                    .unwrap_or_else(|e| error!("Failed to archive season {}: {}", season.id, e));
+            }
+            Err(e) => error!("Failed to serialize season archive: {}", e),
+        }
+    }
+
+    info!("Season {} over, season {} started", season.id, next.id);
+
+    leaderboard::audit(
+        server,
+        actor,
+        "rollover",
+        json!({ "season": archive.season.id, "entries": archive.standings.len() }),
+    )
+    .await;
+    leaderboard::publish(server).await;
+
+    let connections = server.connections.read().await;
+
+    for (_, connection) in connections.iter() {
+        if let Err(e) = connection.send(NetworkMessage::NewSeason(next.clone())) {
+            error!("Failed to send message over WebSocket: {}", e);
+        }
+    }
+
+    archive
+}
+
+pub fn routes(
+    server: Arc<Server>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let with_server = {
+        let server = server.clone();
+        warp::any().map(move || server.clone())
+    };
+
+    let list = warp::path!("seasons")
+        .and(warp::get())
+        .and(with_server.clone())
+        .and_then(list_seasons);
+
+    let season = warp::path!("seasons" / u64)
+        .and(warp::get())
+        .and(with_server)
+        .and_then(get_season);
+
+    let rollover = warp::path!("admin" / "seasons" / "rollover")
+        .and(warp::post())
+        .and(with_admin_actor(server))
+        .and_then(force_rollover);
+
+    list.or(season).or(rollover)
+}
+
+async fn list_seasons(server: Arc<Server>) -> Result<impl Reply, Rejection> {
+    let current = server.season.read().await.clone();
+
+    let archived: HashMap<u64, String> = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => redis_client.hgetall(ARCHIVE_KEY).unwrap_or_default(),
+        None => HashMap::new(),
+    };
+
+    let mut past: Vec<Season> = archived
+        .values()
+        .filter_map(|json| serde_json::from_str::<SeasonArchive>(json).ok())
+        .map(|archive| archive.season)
+        .collect();
+    past.sort_by_key(|season| season.id);
+
+    Ok(warp::reply::json(&json!({
+        "current": current,
+        "past": past,
+    })))
+}
+
+// the current season's standings are live, past ones come from the archive
+async fn get_season(id: u64, server: Arc<Server>) -> Result<impl Reply, Rejection> {
+    let current = server.season.read().await.clone();
+
+    if id == current.id {
+        let standings = match server.redis.lock().await.as_mut() {
+            Some(redis_client) => all_entries(redis_client, &Board::season(id)),
+            None => Vec::new(),
+        };
+
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({
+                "season": current,
+                "standings": standings,
+            })),
+            StatusCode::OK,
+        ));
+    }
+
+    let archive: Option<SeasonArchive> = match server.redis.lock().await.as_mut() {
+        Some(redis_client) => redis_client
+            .hget::<_, _, Option<String>>(ARCHIVE_KEY, id)
+            .ok()
+            .flatten()
+            .and_then(|json| serde_json::from_str(&json).ok()),
+        None => None,
+    };
+
+    match archive {
+        Some(archive) => Ok(warp::reply::with_status(
+            warp::reply::json(&archive),
+            StatusCode::OK,
+        )),
+        None => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({ "error": "unknown season" })),
+            StatusCode::NOT_FOUND,
+        )),
+    }
+}
+
+async fn force_rollover(server: Arc<Server>, actor: String) -> Result<impl Reply, Rejection> {
+    let archive = rollover(&server, &actor).await;
+
+    Ok(warp::reply::json(&json!({
+        "status": "rolled over",
+        "archived": archive.season,
+        "prizes": archive.prizes,
+    })))
+}
@@ -0,0 +1,78 @@
+use std::{collections::HashMap, env, time::Duration};
+
+use tokio::time::Instant;
+use uuid::Uuid;
+
+// a dropped client can come back with its resume token within the grace period and take over
+// its old player
+#[derive(Debug)]
+pub struct Sessions {
+    pub grace: Duration,
+    pub tokens: HashMap<Uuid, Uuid>,
+    pub disconnected: HashMap<Uuid, Instant>,
+}
+
+impl Sessions {
+    pub fn from_env() -> Self {
+        let grace = env::var("RESUME_GRACE_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
+
+        Self {
+            grace,
+            tokens: HashMap::new(),
+            disconnected: HashMap::new(),
+        }
+    }
+
+    pub fn issue(&mut self, client_id: Uuid) -> Uuid {
+        let token = Uuid::new_v4();
+        self.tokens.insert(token, client_id);
+        token
+    }
+
+    pub fn disconnect(&mut self, client_id: Uuid) {
+        self.disconnected.insert(client_id, Instant::now());
+    }
+
+    pub fn resume(&mut self, token: Uuid) -> Option<Uuid> {
+        let client_id = *self.tokens.get(&token)?;
+
+        if let Some(disconnected) = self.disconnected.remove(&client_id) {
+            if disconnected.elapsed() >= self.grace {
+                self.forget(client_id);
+                return None;
+            }
+        }
+
+        Some(client_id)
+    }
+
+    pub fn forget(&mut self, client_id: Uuid) {
+        self.tokens.retain(|_, id| *id != client_id);
+        self.disconnected.remove(&client_id);
+    }
+
+    // forgets every client past its grace period and returns them
+    pub fn expire(&mut self) -> Vec<Uuid> {
+        let grace = self.grace;
+        let expired: Vec<Uuid> = self
+            .disconnected
+            .iter()
+            .filter(|(_, disconnected)| disconnected.elapsed() >= grace)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in &expired {
+            self.forget(*id);
+        }
+
+        expired
+    }
+
+    pub fn waiting(&self) -> Vec<Uuid> {
+        self.disconnected.keys().copied().collect()
+    }
+}
@@ -0,0 +1,35 @@
+use std::env;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnMode {
+    // clients get the rng seed and work out every spawn themselves
+    Seed,
+    // the seed stays on the server and spawns are announced `lookahead` ticks early
+    Stream,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpawnConfig {
+    pub mode: SpawnMode,
+    pub lookahead: u64,
+}
+
+impl SpawnConfig {
+    pub fn from_env() -> Self {
+        let mode = match env::var("SPAWN_MODE").as_deref() {
+            Ok("stream") => SpawnMode::Stream,
+            _ => SpawnMode::Seed,
+        };
+
+        let lookahead = env::var("SPAWN_LOOKAHEAD_TICKS")
+            .ok()
+            .and_then(|ticks| ticks.parse().ok())
+            .unwrap_or(20);
+
+        Self { mode, lookahead }
+    }
+
+    pub fn reveal_seed(&self) -> bool {
+        self.mode == SpawnMode::Seed
+    }
+}
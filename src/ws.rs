@@ -1,4 +1,9 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
 use futures_util::{SinkExt, StreamExt};
 use log::{error, info, warn};
@@ -8,136 +13,414 @@ use std::cmp::Ordering;
 
 use speedy::{Readable, Writable};
 use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::time::Instant;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use uuid::Uuid;
-use warp::ws::{Message, WebSocket};
+use warp::{
+    ws::{Message, WebSocket},
+    Filter, Rejection, Reply,
+};
 use zebedee_rust::ln_address::LnAddress;
 
-use crate::game_loop::PlayerEntity;
-use crate::messages::{self, NetworkMessage, SyncMessage};
+use crate::auth::{self, SessionClaims};
+use crate::bans::{self, ban_messages, Ban, BanKind};
+use crate::challenge::{difficulty_for, new_challenge, verify, PendingChallenge};
+use crate::entry::admit;
+use crate::idle::{Activity, IdleAction};
+use crate::lifecycle;
+use crate::limits::{self, ConnectionPermit, TokenBucket};
+use crate::login;
+use crate::messages::{self, DisconnectReason, ErrorCode, NetworkMessage, Profile, SyncMessage};
+use crate::names::{self, NameRules};
 use crate::{messages::ClientMessage, Server};
 
-pub async fn new_websocket(ws: WebSocket, server: Arc<Server>) {
-    let (mut ws_tx, mut ws_rx) = ws.split();
+// consecutive undecodable messages before the connection is closed
+const MAX_INVALID_MESSAGES: u32 = 10;
+// clients connecting with ?version=2 or later get every message, older ones only what
+// `NetworkMessage::legacy` allows
+pub const PROTOCOL_VERSION: u32 = 2;
 
-    let (tx, rx) = mpsc::unbounded_channel();
-    let mut rx = UnboundedReceiverStream::new(rx);
+pub fn routes(
+    server: Arc<Server>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let server = warp::any().map(move || server.clone());
 
-    let tx_clone = tx.clone();
+    warp::path("run")
+        .and(warp::ws())
+        .and(server)
+        .and(warp::addr::remote())
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(warp::header::optional::<String>("origin"))
+        .and(warp::header::optional::<String>("sec-websocket-protocol"))
+        .and(warp::query::<HashMap<String, String>>())
+        .then(
+            |ws: warp::ws::Ws,
+             server: Arc<Server>,
+             addr: Option<SocketAddr>,
+             forwarded_for: Option<String>,
+             origin: Option<String>,
+             protocols: Option<String>,
+             mut query: HashMap<String, String>| async move {
+                let authorized =
+                    match auth::authorize(&server.auth, origin, query.remove("token"), protocols) {
+                        Ok(authorized) => authorized,
+                        Err((status, reason)) => {
+                            return warp::reply::with_status(reason, status).into_response()
+                        }
+                    };
 
-    let client_id = Uuid::new_v4();
-    {
-        let mut connections = server.connections.write().await;
-        connections.insert(client_id, tx);
-    }
+                let ip = limits::client_ip(&server.limits, addr, forwarded_for);
 
-    let current_tick = server.tick.load(std::sync::atomic::Ordering::Relaxed);
+                // banned clients count against the limits too, or they could hold open any
+                // number of sockets while being told why
+                let permit = match limits::acquire(server.clone(), ip).await {
+                    Ok(permit) => permit,
+                    Err((status, reason)) => {
+                        return warp::reply::with_status(reason, status).into_response()
+                    }
+                };
+
+                if let Some(ip) = ip {
+                    if let Some(ban) = bans::check(&server, BanKind::Ip, &ip.to_string()).await {
+                        info!("Refusing banned {}: {}", ip, ban.reason);
+                        return ws
+                            .on_upgrade(move |socket| refuse(socket, ban, permit))
+                            .into_response();
+                    }
+                }
 
-    let seed = server.seed.load(std::sync::atomic::Ordering::Relaxed);
+                let extended = query
+                    .get("version")
+                    .and_then(|version| version.parse::<u32>().ok())
+                    .is_some_and(|version| version >= PROTOCOL_VERSION);
 
-    let high_scores = server.high_scores.read().await.clone();
+                let max_frame_bytes = server.limits.max_frame_bytes;
+                let claims = authorized.claims;
+                let reply = ws
+                    .max_frame_size(max_frame_bytes)
+                    .max_message_size(max_frame_bytes)
+                    .on_upgrade(move |socket| {
+                        new_websocket(socket, server, ip, claims, extended, permit)
+                    });
 
-    let new_game = NewGame::new(
-        client_id,
-        current_tick,
-        seed,
-        high_scores,
-        server.objects.lock().await.clone().unwrap(),
-    );
+                match authorized.protocol {
+                    Some(protocol) => {
+                        warp::reply::with_header(reply, "sec-websocket-protocol", protocol)
+                            .into_response()
+                    }
+                    None => reply.into_response(),
+                }
+            },
+        )
+}
 
-    tx_clone
-        .send(NetworkMessage::NewGame(new_game))
-        .expect("Failed to send new game message");
+// the permit holds this connection's slot until the socket is done
+async fn new_websocket(
+    ws: WebSocket,
+    server: Arc<Server>,
+    ip: Option<IpAddr>,
+    mut claims: Option<SessionClaims>,
+    extended: bool,
+    _permit: ConnectionPermit,
+) {
+    let mut account = claims.as_ref().map(|claims| claims.subject.clone());
+
+    let (mut ws_tx, mut ws_rx) = ws.split();
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut rx = UnboundedReceiverStream::new(rx);
+
+    let tx_clone = tx.clone();
+
+    let (mut client_id, resume_token) = lifecycle::register(&server, tx).await;
+
+    let ready = send_new_game(&server, client_id, resume_token, &tx_clone).await;
 
     tokio::task::spawn(async move {
         let mut interval = tokio::time::interval(Duration::from_secs(5));
         loop {
             tokio::select! {
                 _ = interval.tick() => {
-                    let ping = NetworkMessage::Ping;
-                    let ping = ping.write_to_vec().unwrap();
+                    let ping = match NetworkMessage::Ping.write_to_vec() {
+                        Ok(ping) => ping,
+                        Err(e) => {
+                            error!("Failed to encode ping: {}", e);
+                            continue;
+                        }
+                    };
                     if let Err(e) = ws_tx.send(Message::binary(ping)).await {
                         error!("Failed to send ping: {}", e);
                         break;
                     }
+
+                    // browsers answer protocol pings on their own, so even clients that never
+                    // send a heartbeat keep the read timeout from firing
+                    if let Err(e) = ws_tx.send(Message::ping(Vec::new())).await {
+                        error!("Failed to send ping: {}", e);
+                        break;
+                    }
                 }
                 Some(message) = rx.next() => {
-                    let message = message.write_to_vec().unwrap();
-                    match ws_tx.send(Message::binary(message)).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Failed to send message over WebSocket: {}", e);
-                            break;
+                    let reason = match &message {
+                        NetworkMessage::Disconnect(reason) => Some(*reason),
+                        _ => None,
+                    };
+
+                    // older clients get the old encoding or nothing, a disconnect still closes
+                    let encoded = if extended {
+                        Some(message.write_to_vec())
+                    } else {
+                        message.legacy().map(|legacy| legacy.write_to_vec())
+                    };
+
+                    if let Some(encoded) = encoded {
+                        let message = match encoded {
+                            Ok(message) => message,
+                            Err(e) => {
+                                error!("Failed to encode message: {}", e);
+                                continue;
+                            }
+                        };
+                        match ws_tx.send(Message::binary(message)).await {
+                            Ok(_) => {}
+                            Err(e) => {
+                                error!("Failed to send message over WebSocket: {}", e);
+                                break;
+                            }
+                        }
+                    }
+
+                    if let Some(reason) = reason {
+                        let close = Message::close_with(reason.close_code(), reason.description());
+                        if let Err(e) = ws_tx.send(close).await {
+                            error!("Failed to close WebSocket: {}", e);
                         }
+                        break;
                     }
                 }
             }
         }
     });
 
-    while let Some(result) = ws_rx.next().await {
+    if !ready {
+        send_message(
+            &tx_clone,
+            NetworkMessage::Disconnect(DisconnectReason::Unavailable),
+        );
+        lifecycle::evict(&server, client_id, &tx_clone).await;
+        return;
+    }
+
+    let mut pending_challenge: Option<PendingChallenge> = None;
+
+    let mut idle_check = tokio::time::interval(Duration::from_secs(1));
+    let mut last_seen = Instant::now();
+    let mut activity = Activity::new();
+    let mut invalid_messages = 0;
+    let mut message_bucket = TokenBucket::new(&server.limits);
+    let mut dropped_messages: u64 = 0;
+
+    let close_reason = loop {
+        let result = tokio::select! {
+            result = ws_rx.next() => match result {
+                Some(result) => result,
+                None => break None,
+            },
+            _ = idle_check.tick() => {
+                if last_seen.elapsed() >= server.idle.read_timeout {
+                    break Some(DisconnectReason::HeartbeatTimeout);
+                }
+
+                match activity.check(&server.idle) {
+                    Some(IdleAction::Warn(secs_left)) => {
+                        info!("{} is idle, kicking in {}s", client_id, secs_left);
+                        if let Err(e) = tx_clone.send(NetworkMessage::IdleWarning(secs_left)) {
+                            error!("Failed to send idle warning: {}", e);
+                        }
+                    }
+                    Some(IdleAction::Kick) => break Some(DisconnectReason::Idle),
+                    None => {}
+                }
+                continue;
+            }
+        };
+
+        // anything from the client, heartbeats and protocol pongs included, shows it's alive
+        last_seen = Instant::now();
+
         match result {
             Ok(msg) => {
-                if msg.is_binary() {
-                    match ClientMessage::read_from_buffer(msg.as_bytes()) {
+                if msg.is_binary() && !message_bucket.try_take() {
+                    if dropped_messages == 0 {
+                        warn!("{} is over the message rate, dropping", client_id);
+                    }
+                    dropped_messages += 1;
+                    server
+                        .connection_stats
+                        .dropped_messages
+                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                } else if msg.is_binary() {
+                    let message = ClientMessage::read_from_buffer(msg.as_bytes());
+                    if message.is_ok() {
+                        invalid_messages = 0;
+                    }
+
+                    match message {
                         Ok(ClientMessage::PlayerName(name)) => {
-                            let ln_address = LnAddress {
-                                address: name.clone(),
-                            };
+                            if lifecycle::playing(&server, client_id).await {
+                                warn!("{} is already playing, ignoring another join", client_id);
+                                continue;
+                            }
 
-                            let valid_email_address = ln_address.validate();
+                            let profile = legacy_profile(&server.names, name);
+                            if banned_profile(&server, client_id, &profile, &tx_clone).await {
+                                break Some(DisconnectReason::Banned);
+                            }
 
-                            let server_clone = server.clone();
+                            pending_challenge = join(
+                                &server,
+                                client_id,
+                                profile,
+                                ip,
+                                claims.as_ref(),
+                                account.clone(),
+                                &tx_clone,
+                            )
+                            .await;
+                        }
+                        Ok(ClientMessage::Profile(profile)) => {
+                            if lifecycle::playing(&server, client_id).await {
+                                warn!("{} is already playing, ignoring another join", client_id);
+                                continue;
+                            }
+
+                            if banned_profile(&server, client_id, &profile, &tx_clone).await {
+                                break Some(DisconnectReason::Banned);
+                            }
 
-                            match valid_email_address {
-                                Ok(_) => {
-                                    tokio::spawn(async move {
-                                        info!("Validating LN address: {}", name);
-                                        let validate_response = server_clone
-                                            .zebedee_client
-                                            .lock()
+                            pending_challenge = join(
+                                &server,
+                                client_id,
+                                profile,
+                                ip,
+                                claims.as_ref(),
+                                account.clone(),
+                                &tx_clone,
+                            )
+                            .await;
+                        }
+                        Ok(ClientMessage::ChallengeSolution(nonce)) => {
+                            match pending_challenge.take() {
+                                Some(pending)
+                                    if pending.issued.elapsed() < server.challenge.ttl
+                                        && verify(&pending.challenge, nonce) =>
+                                {
+                                    match pending.ln_address {
+                                        Some(ln_address) => validate_ln_address(
+                                            server.clone(),
+                                            client_id,
+                                            pending.display_name,
+                                            ln_address,
+                                            ip,
+                                            account.clone(),
+                                            tx_clone.clone(),
+                                        ),
+                                        None => {
+                                            admit(
+                                                server.clone(),
+                                                client_id,
+                                                pending.display_name,
+                                                None,
+                                                ip,
+                                                account.clone(),
+                                            )
                                             .await
-                                            .validate_ln_address(&ln_address)
-                                            .await;
-
-                                        match validate_response {
-                                            Ok(res) => {
-                                                info!("Valid LN address: {:?}", res.data);
-                                                let player = PlayerEntity::new(
-                                                    client_id,
-                                                    name.to_string(),
-                                                    true,
-                                                )
-                                                .await;
-                                                let mut player_names =
-                                                    server_clone.player_names.lock().await;
-                                                player_names.insert(client_id, player);
-                                            }
-                                            Err(e) => {
-                                                error!("Invalid LN address: {}", e);
-                                                let player = PlayerEntity::new(
-                                                    client_id,
-                                                    name.to_string(),
-                                                    false,
-                                                )
-                                                .await;
-                                                let mut player_names =
-                                                    server_clone.player_names.lock().await;
-                                                player_names.insert(client_id, player);
-                                            }
-                                        };
-                                    });
+                                        }
+                                    }
+                                }
+                                Some(_) => {
+                                    warn!("Invalid challenge solution from {}", client_id);
+                                    send_error(
+                                        &tx_clone,
+                                        ErrorCode::InvalidChallenge,
+                                        "challenge solution is wrong or expired",
+                                    );
                                 }
-                                Err(e) => {
-                                    error!("{:?}", e);
-                                    let player =
-                                        PlayerEntity::new(client_id, name.to_string(), false).await;
-                                    let mut player_names = server.player_names.lock().await;
-                                    player_names.insert(client_id, player.clone());
+                                None => {
+                                    warn!("Unexpected challenge solution from {}", client_id);
+                                    send_error(
+                                        &tx_clone,
+                                        ErrorCode::InvalidChallenge,
+                                        "no challenge pending",
+                                    );
                                 }
-                            };
+                            }
+                        }
+                        Ok(ClientMessage::Resume(token)) => {
+                            let resumed = server.sessions.lock().await.resume(token);
+
+                            match resumed {
+                                Some(old_id) if old_id != client_id => {
+                                    lifecycle::reattach(
+                                        &server,
+                                        client_id,
+                                        old_id,
+                                        tx_clone.clone(),
+                                    )
+                                    .await;
+
+                                    info!("{} resumed as {}", client_id, old_id);
+                                    client_id = old_id;
+
+                                    if !send_new_game(&server, client_id, token, &tx_clone).await {
+                                        break Some(DisconnectReason::Unavailable);
+                                    }
+
+                                    let current_tick =
+                                        server.tick.load(std::sync::atomic::Ordering::Relaxed);
+                                    sync_msg(0, current_tick, &tx_clone).await;
+                                }
+                                Some(_) => {}
+                                None => {
+                                    warn!("Invalid resume token from {}", client_id);
+                                    send_error(
+                                        &tx_clone,
+                                        ErrorCode::InvalidResumeToken,
+                                        "resume token is unknown or expired",
+                                    );
+                                }
+                            }
+                        }
+                        Ok(ClientMessage::Heartbeat) => {}
+                        Ok(ClientMessage::Login) => {
+                            match login::login_challenge(&server, client_id).await {
+                                Some(challenge) => send_message(
+                                    &tx_clone,
+                                    NetworkMessage::LoginChallenge(challenge),
+                                ),
+                                None => send_error(
+                                    &tx_clone,
+                                    ErrorCode::LoginUnavailable,
+                                    "failed to create a login challenge",
+                                ),
+                            }
+                        }
+                        Ok(ClientMessage::Authenticate(token)) => {
+                            match server.auth.verify(&token) {
+                                Ok(verified) => {
+                                    info!("{} authenticated as {}", client_id, verified.subject);
+                                    account = Some(verified.subject.clone());
+                                    claims = Some(verified);
+                                }
+                                Err(reason) => {
+                                    warn!("Invalid session token from {}: {}", client_id, reason);
+                                    send_error(&tx_clone, ErrorCode::InvalidSessionToken, reason);
+                                }
+                            }
                         }
                         Ok(ClientMessage::PlayerInput(input)) => {
+                            activity.record_input(&input);
+
                             let current_tick =
                                 server.tick.load(std::sync::atomic::Ordering::Relaxed);
 
@@ -156,39 +439,339 @@ pub async fn new_websocket(ws: WebSocket, server: Arc<Server>) {
                             }
 
                             if input.in_game {
-                                {
-                                    let mut inputs = server.player_inputs.lock().await;
-                                    let player_inputs =
-                                        inputs.entry(client_id).or_insert_with(Vec::new);
-                                    player_inputs.push(input);
-                                }
+                                lifecycle::push_input(&server, client_id, input).await;
                             }
                         }
                         Err(e) => {
                             error!("error reading message: {}", e);
+                            send_error(&tx_clone, ErrorCode::InvalidMessage, e.to_string());
+
+                            invalid_messages += 1;
+                            if invalid_messages >= MAX_INVALID_MESSAGES {
+                                break Some(DisconnectReason::ProtocolViolation);
+                            }
                         }
                     }
-                } else {
+                } else if !msg.is_pong() && !msg.is_close() {
                     error!("other message: {:?}", msg);
+                    send_error(
+                        &tx_clone,
+                        ErrorCode::UnsupportedFrame,
+                        "only binary messages are supported",
+                    );
                 }
             }
             Err(e) => {
                 error!("websocket error(uid={}): {}", client_id, e);
-                break;
+                break None;
             }
         };
+    };
+
+    match close_reason {
+        Some(reason) => {
+            info!("closing {}: {}", client_id, reason.description());
+            send_message(&tx_clone, NetworkMessage::Disconnect(reason));
+        }
+        None => info!("player disconnected: {}", client_id),
+    }
+
+    if dropped_messages > 0 {
+        info!("dropped {} messages from {}", dropped_messages, client_id);
+    }
+
+    match close_reason {
+        Some(DisconnectReason::Idle | DisconnectReason::Banned | DisconnectReason::Unavailable) => {
+            lifecycle::evict(&server, client_id, &tx_clone).await
+        }
+        _ => lifecycle::disconnect(&server, client_id, &tx_clone).await,
     }
+}
+
+// false if there is no game to join yet, the caller closes the connection
+async fn send_new_game(
+    server: &Server,
+    client_id: Uuid,
+    resume_token: Uuid,
+    tx: &UnboundedSender<NetworkMessage>,
+) -> bool {
+    let current_tick = server.tick.load(std::sync::atomic::Ordering::Relaxed);
+
+    let seed = server
+        .spawns
+        .reveal_seed()
+        .then(|| server.seed.load(std::sync::atomic::Ordering::Relaxed));
+
+    let Some(objects) = server.objects.lock().await.clone() else {
+        // the game loop hasn't produced its first objects yet
+        warn!("Turning away {}, no objects yet", client_id);
+        return false;
+    };
 
-    info!("player disconnected: {}", client_id);
+    let high_scores = server.high_scores.read().await.clone();
+
+    let new_game = NewGame::new(
+        client_id,
+        current_tick,
+        seed,
+        high_scores,
+        objects,
+        resume_token,
+    );
+
+    send_message(tx, NetworkMessage::NewGame(new_game));
+
+    let seed_update = server
+        .fair
+        .lock()
+        .await
+        .seed_update(server.spawns.reveal_seed());
+
+    send_message(tx, NetworkMessage::SeedUpdate(seed_update));
+
+    if let Some(upcoming_spawns) = server.upcoming_spawns.lock().await.clone() {
+        send_message(tx, NetworkMessage::ObjectSpawns(upcoming_spawns));
+    }
+
+    true
+}
+
+// browsers can't read the body of a refused upgrade, so banned clients are let in just long
+// enough to be told why, holding their connection slot until then
+async fn refuse(mut ws: WebSocket, ban: Ban, _permit: ConnectionPermit) {
+    for message in ban_messages(&ban) {
+        match message.write_to_vec() {
+            Ok(message) => {
+                if let Err(e) = ws.send(Message::binary(message)).await {
+                    error!("Failed to send message over WebSocket: {}", e);
+                    return;
+                }
+            }
+            Err(e) => error!("Failed to encode message: {}", e),
+        }
+    }
+
+    let reason = DisconnectReason::Banned;
+    if let Err(e) = ws
+        .send(Message::close_with(
+            reason.close_code(),
+            reason.description(),
+        ))
+        .await
     {
-        let mut connections = server.connections.write().await;
-        connections.remove(&client_id);
+        error!("Failed to close WebSocket: {}", e);
+    }
+}
+
+async fn banned_profile(
+    server: &Server,
+    client_id: Uuid,
+    profile: &Profile,
+    tx: &UnboundedSender<NetworkMessage>,
+) -> bool {
+    let ban = bans::profile_ban(server, &profile.display_name, profile.ln_address.as_deref()).await;
+
+    match ban {
+        Some(ban) => {
+            info!("Refusing banned profile from {}: {}", client_id, ban.reason);
+            let [error, _] = ban_messages(&ban);
+            send_message(tx, error);
+            true
+        }
+        None => false,
     }
 }
 
+// older clients send one string that is both their name and, if it parses, their payout
+// address. They can't show a name error, so a name that isn't allowed plays anonymously
+fn legacy_profile(rules: &NameRules, name: String) -> Profile {
+    if names::is_ln_address(&name) {
+        Profile {
+            display_name: names::anonymous_name(&name),
+            ln_address: Some(name),
+        }
+    } else if rules.validate(&name).is_err() {
+        Profile {
+            display_name: names::anonymous_name(&name),
+            ln_address: None,
+        }
+    } else {
+        Profile {
+            display_name: name,
+            ln_address: None,
+        }
+    }
+}
+
+// admits the player straight away, or challenges them first if they can earn payouts and
+// challenges are on
+async fn join(
+    server: &Arc<Server>,
+    client_id: Uuid,
+    profile: Profile,
+    ip: Option<IpAddr>,
+    claims: Option<&SessionClaims>,
+    account: Option<String>,
+    tx: &UnboundedSender<NetworkMessage>,
+) -> Option<PendingChallenge> {
+    let display_name = match server.names.validate(&profile.display_name) {
+        Ok(display_name) => display_name,
+        Err(reason) => {
+            send_error(tx, ErrorCode::InvalidName, reason);
+            return None;
+        }
+    };
+
+    let claimed = match login::check_name(server, &display_name, account.as_deref()).await {
+        Ok(_) => names::reserve(server, client_id, &display_name).await,
+        Err(reason) => Err(reason),
+    };
+
+    if let Err(reason) = claimed {
+        warn!("{} rejected: {}", client_id, reason);
+        send_error(tx, ErrorCode::NameTaken, reason);
+        return None;
+    }
+
+    let ln_address = match profile.ln_address {
+        Some(ln_address) => ln_address.trim().to_string(),
+        // a balance claimable over LNURL-withdraw is a payout too
+        None if server.challenge.enabled && server.lnurl.withdraw_enabled => {
+            return Some(challenge(server, client_id, display_name, None, ip, tx).await);
+        }
+        None => {
+            admit(server.clone(), client_id, display_name, None, ip, account).await;
+            return None;
+        }
+    };
+
+    let payout_address = LnAddress {
+        address: ln_address.clone(),
+    }
+    .validate()
+    .map_err(|e| format!("{:?}", e))
+    .and_then(|_| {
+        server
+            .auth
+            .payout_address_allowed(claims, &ln_address)
+            .map_err(str::to_string)
+    });
+
+    let payout_address = match payout_address {
+        Ok(_) => login::check_payout_address(server, &ln_address, claims, account.as_deref()).await,
+        Err(reason) => Err(reason),
+    };
+
+    if let Err(reason) = payout_address {
+        // the reason can contain the address, which stays out of the logs
+        warn!("{} playing without payouts", client_id);
+        send_error(
+            tx,
+            ErrorCode::InvalidLnAddress,
+            format!("{}, playing without payouts", reason),
+        );
+        admit(server.clone(), client_id, display_name, None, ip, account).await;
+        return None;
+    }
+
+    if !server.challenge.enabled {
+        validate_ln_address(
+            server.clone(),
+            client_id,
+            display_name,
+            ln_address,
+            ip,
+            account,
+            tx.clone(),
+        );
+        return None;
+    }
+
+    Some(challenge(server, client_id, display_name, Some(ln_address), ip, tx).await)
+}
+
+async fn challenge(
+    server: &Server,
+    client_id: Uuid,
+    display_name: String,
+    ln_address: Option<String>,
+    ip: Option<IpAddr>,
+    tx: &UnboundedSender<NetworkMessage>,
+) -> PendingChallenge {
+    let difficulty = difficulty_for(server, ln_address.as_deref(), ip).await;
+    let challenge = new_challenge(difficulty);
+
+    info!("Challenging {} at difficulty {}", client_id, difficulty);
+    send_message(tx, NetworkMessage::Challenge(challenge.clone()));
+
+    PendingChallenge {
+        challenge,
+        display_name,
+        ln_address,
+        issued: Instant::now(),
+    }
+}
+
+fn validate_ln_address(
+    server: Arc<Server>,
+    client_id: Uuid,
+    display_name: String,
+    ln_address: String,
+    ip: Option<IpAddr>,
+    account: Option<String>,
+    tx: UnboundedSender<NetworkMessage>,
+) {
+    tokio::spawn(async move {
+        info!("Validating LN address for {}", client_id);
+        let validate_response = server
+            .payment_provider
+            .validate_ln_address(&ln_address)
+            .await;
+
+        match validate_response {
+            Ok(_) => {
+                admit(
+                    server,
+                    client_id,
+                    display_name,
+                    Some(ln_address),
+                    ip,
+                    account,
+                )
+                .await;
+            }
+            Err(e) => {
+                error!("Invalid LN address for {}: {}", client_id, e);
+                send_error(
+                    &tx,
+                    ErrorCode::InvalidLnAddress,
+                    format!("{}, playing without payouts", e),
+                );
+                admit(server, client_id, display_name, None, ip, account).await;
+            }
+        };
+    });
+}
+
 async fn sync_msg(tick_adjustment: i64, current_tick: u64, tx: &UnboundedSender<NetworkMessage>) {
     let sync_msg = SyncMessage::new(tick_adjustment, current_tick);
 
-    tx.send(NetworkMessage::SyncClient(sync_msg))
-        .expect("Failed to send sync message");
+    send_message(tx, NetworkMessage::SyncClient(sync_msg));
+}
+
+// the receiving half only goes away once the socket is closing, so a failed send is just logged
+fn send_message(tx: &UnboundedSender<NetworkMessage>, message: NetworkMessage) {
+    if let Err(e) = tx.send(message) {
+        error!("Failed to send message: {}", e);
+    }
+}
+
+fn send_error(tx: &UnboundedSender<NetworkMessage>, code: ErrorCode, message: impl Into<String>) {
+    send_message(
+        tx,
+        NetworkMessage::Error {
+            code,
+            message: message.into(),
+        },
+    );
 }